@@ -1,57 +1,199 @@
-use std::{sync::{Mutex, Arc}, f64::consts::PI};
+use std::sync::{Mutex, Arc};
 
 use rodio::{OutputStreamHandle, Sink, OutputStream, Source};
 
+use self::{
+    pulse::{Pulse, PulseChannel},
+    triangle::Triangle,
+    noise::Noise,
+    dmc::Dmc,
+    frame_counter::{FrameCounter, FrameResult},
+    mixer::{Mixer, FilterChain},
+    blip::BlipBuf,
+};
+
+pub use self::{vrc6::Vrc6, vrc7::Vrc7, mmc5::Mmc5, fds::Fds, n163::{N163, N163Mixing}, s5b::S5B};
+
+pub use self::mixer::FilterSettings;
+
+mod envelope;
+mod length_counter;
+mod pulse;
+mod triangle;
+mod noise;
+mod dmc;
+mod frame_counter;
+mod mixer;
+mod blip;
+mod vrc6;
+mod vrc7;
+mod mmc5;
+mod fds;
+mod n163;
+mod s5b;
+
+/// Called on the audio thread with the board to write registers to, e.g. a
+/// music engine's tick
+pub type DriverFn = Box<dyn FnMut(&mut Board) + Send>;
+
+struct Driver{
+    /// ticks per second, the CPU cycles between ticks follow the board's
+    /// region
+    rate: f64,
+    countdown: f64,
+    tick: DriverFn,
+}
+
 pub struct HardwareInterface{
-    h_2a03: Arc<Mutex<Apu>>,
+    board: Arc<Mutex<Board>>,
+    filters: Arc<Mutex<FilterChain>>,
+    driver: Arc<Mutex<Option<Driver>>>,
     #[allow(unused)]
     stream_handle: OutputStreamHandle,
     #[allow(unused)]
     stream: OutputStream,
 }
 
+impl Default for HardwareInterface{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl HardwareInterface{
     pub fn new() -> Self{
         let (stream, stream_handle) = OutputStream::try_default().unwrap();
         let sink = Sink::try_new(&stream_handle).unwrap();
-        let mut apu = Apu::new();
-        apu.reset();
-        
-        apu.write_register(0x4000, 0x3F);
-        apu.write_register(0x4001, 0x08);
-        apu.write_register(0x4002, 0x5D);
-        apu.write_register(0x4003, 0x00);
-        apu.write_register(0x4015, 0x01);
-        let source = Arc::new(Mutex::new(apu));
-        sink.append(Thing{apu: source.clone() });
+        let source = Arc::new(Mutex::new(Board::new()));
+        let thing = Thing::new(source.clone(), Default::default());
+        let filters = thing.filters.clone();
+        let driver = thing.driver.clone();
+        sink.append(thing);
         sink.detach();
         Self { 
-            h_2a03: source, 
+            board: source, 
+            filters,
+            driver,
             stream_handle,
             stream
         }
     }
     pub fn reset(&mut self) {
-        self.h_2a03.lock().unwrap().reset();
+        self.board.lock().unwrap().reset();
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8){
+        self.board.lock().unwrap().write_register(address, value);
+    }
+
+    pub fn read_status(&mut self) -> u8{
+        self.board.lock().unwrap().apu.read_status()
+    }
+
+    pub fn filters(&self) -> FilterSettings{
+        self.filters.lock().unwrap().settings()
+    }
+
+    pub fn set_filters(&mut self, settings: FilterSettings){
+        self.filters.lock().unwrap().set_settings(settings);
+    }
+
+    pub fn set_n163_mixing(&mut self, mixing: N163Mixing){
+        self.board.lock().unwrap().set_n163_mixing(mixing);
+    }
+
+    pub fn set_region(&mut self, region: Region){
+        self.board.lock().unwrap().set_region(region);
+    }
+
+    /// runs `tick` `rate` times a second in step with the generated audio
+    pub fn set_driver(&mut self, rate: f64, tick: DriverFn){
+        *self.driver.lock().unwrap() = Some(Driver{
+            rate,
+            countdown: 0.0,
+            tick,
+        });
+    }
+
+    pub fn clear_driver(&mut self){
+        *self.driver.lock().unwrap() = None;
     }
 }
 
-struct Thing{
-    apu: Arc<Mutex<Apu>>,
+/// CPU cycles the APU is run for each time the output runs dry
+const CHUNK_CYCLES: u32 = 4096;
+
+/// Runs the board at the CPU clock and resamples its output to
+/// `SAMPLE_RATE` through a band-limited step buffer.
+pub(crate) struct Thing{
+    board: Arc<Mutex<Board>>,
+    filters: Arc<Mutex<FilterChain>>,
+    driver: Arc<Mutex<Option<Driver>>>,
+    blip: BlipBuf,
+    last_output: f32,
+    samples: Vec<f32>,
+    position: usize,
+}
+
+impl Thing{
+    pub(crate) fn new(board: Arc<Mutex<Board>>, settings: FilterSettings) -> Self{
+        let mut filters = FilterChain::new(SAMPLE_RATE as f32);
+        filters.set_settings(settings);
+        Self {
+            board,
+            filters: Arc::new(Mutex::new(filters)),
+            driver: Arc::new(Mutex::new(None)),
+            blip: BlipBuf::new(CPU_CLOCK_NTSC, SAMPLE_RATE as f64),
+            last_output: 0.0,
+            samples: Vec::new(),
+            position: 0,
+        }
+    }
+
+    fn render_chunk(&mut self){
+        {
+            let mut board = self.board.lock().unwrap();
+            let mut driver = self.driver.lock().unwrap();
+            let clock = board.region().cpu_clock();
+            self.blip.set_clock_rate(clock, SAMPLE_RATE as f64);
+            for time in 0..CHUNK_CYCLES{
+                if let Some(driver) = driver.as_mut(){
+                    driver.countdown -= 1.0;
+                    if driver.countdown <= 0.0{
+                        driver.countdown += clock / driver.rate;
+                        (driver.tick)(&mut board);
+                    }
+                }
+                board.clock();
+                let out = board.output();
+                if out != self.last_output{
+                    self.blip.add_delta(time, out - self.last_output);
+                    self.last_output = out;
+                }
+            }
+        }
+        self.blip.end_frame(CHUNK_CYCLES);
+
+        self.samples.resize(self.blip.samples_avail(), 0.0);
+        let count = self.blip.read_samples(&mut self.samples);
+        self.samples.truncate(count);
+        let mut filters = self.filters.lock().unwrap();
+        for sample in self.samples.iter_mut(){
+            *sample = filters.process(*sample);
+        }
+        self.position = 0;
+    }
 }
 
 impl Iterator for Thing{
     type Item = f32;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let out = self.apu.lock().unwrap().next_sample();
-        //let e = std::time::SystemTime::UNIX_EPOCH;
-        //let dur = std::time::SystemTime::duration_since(&std::time::SystemTime::now(), e).unwrap();
-        //let wave = (440.0 * (self.sample as f64) / 44100.0 * PI * 2.0).sin();
-        println!("{}", out);
-        //self.sample += 1;
-        //Option::Some(wave as f32)
-        Option::Some(out)
+        while self.position >= self.samples.len(){
+            self.render_chunk();
+        }
+        self.position += 1;
+        Option::Some(self.samples[self.position - 1])
     }
 }
 
@@ -65,7 +207,7 @@ impl Source for Thing{
     }
 
     fn sample_rate(&self) -> u32 {
-        44100
+        SAMPLE_RATE
     }
 
     fn total_duration(&self) -> Option<std::time::Duration> {
@@ -74,40 +216,361 @@ impl Source for Thing{
 }
 
 
-struct Apu{
-    reg: [u8; 15]
+/// The console variant, selects the CPU clock and the NTSC or PAL tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Region{
+    #[default]
+    Ntsc,
+    Pal,
+    /// the famiclone sold in Russia, a PAL rate CPU clock and picture but
+    /// an APU with the NTSC tables
+    Dendy,
+}
+
+impl Region{
+    /// MACHINE 0 is NTSC and 1 is PAL, FamiTracker writes nothing for a
+    /// Dendy so 2 is taken to mean one
+    pub fn from_machine(machine: u32) -> Self{
+        match machine{
+            1 => Region::Pal,
+            2 => Region::Dendy,
+            _ => Region::Ntsc,
+        }
+    }
+
+    pub fn cpu_clock(&self) -> f64{
+        match self{
+            Region::Ntsc => CPU_CLOCK_NTSC,
+            Region::Pal => CPU_CLOCK_PAL,
+            Region::Dendy => CPU_CLOCK_DENDY,
+        }
+    }
+}
+
+pub const CPU_CLOCK_NTSC: f64 = 1_789_773.0;
+pub const CPU_CLOCK_PAL: f64 = 1_662_607.0;
+pub const CPU_CLOCK_DENDY: f64 = 1_773_448.0;
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// One step of the VRC6 DAC, sized so a full volume VRC6 pulse is as loud
+/// as a full volume 2A03 pulse
+const VRC6_LEVEL: f32 = 95.52 / (8128.0 / 15.0 + 100.0) / 15.0;
+/// the FDS at full volume swings about 2.4 times as far as a full volume
+/// 2A03 pulse
+const FDS_LEVEL: f32 = 2.4 * 95.52 / (8128.0 / 15.0 + 100.0);
+/// a full volume VRC7 channel swings as far as a full volume 2A03 pulse
+const VRC7_LEVEL: f32 = 95.52 / (8128.0 / 15.0 + 100.0) / 2.0;
+/// boards mix the N163 at different levels, this puts a lone full volume
+/// channel playing a full swing square level with a full volume 2A03 pulse
+const N163_LEVEL: f32 = 95.52 / (8128.0 / 15.0 + 100.0) / 225.0;
+/// a 5B square at full volume is as loud as a full volume 2A03 pulse
+const S5B_LEVEL: f32 = 95.52 / (8128.0 / 15.0 + 100.0);
+
+/// The 2A03 plus whatever expansion audio the cartridge carries, with
+/// register writes routed by address
+pub struct Board{
+    pub apu: Apu,
+    pub vrc6: Option<Vrc6>,
+    pub vrc7: Option<Vrc7>,
+    pub mmc5: Option<Mmc5>,
+    pub fds: Option<Fds>,
+    pub n163: Option<N163>,
+    pub s5b: Option<S5B>,
+    n163_mixing: N163Mixing,
+    region: Region,
+}
+
+impl Board{
+    pub fn new() -> Self{
+        Self {
+            apu: Apu::new(Region::Ntsc),
+            vrc6: None,
+            vrc7: None,
+            mmc5: None,
+            fds: None,
+            n163: None,
+            s5b: None,
+            n163_mixing: N163Mixing::default(),
+            region: Region::Ntsc,
+        }
+    }
+
+    /// resets every chip, keeping the expansion in place
+    pub fn reset(&mut self){
+        self.apu.reset();
+        if let Some(vrc6) = self.vrc6.as_mut(){
+            *vrc6 = Vrc6::new();
+        }
+        if let Some(vrc7) = self.vrc7.as_mut(){
+            *vrc7 = Vrc7::new();
+        }
+        if let Some(mmc5) = self.mmc5.as_mut(){
+            *mmc5 = Mmc5::new();
+        }
+        if let Some(fds) = self.fds.as_mut(){
+            *fds = Fds::new();
+        }
+        if let Some(n163) = self.n163.as_mut(){
+            *n163 = N163::new(self.n163_mixing);
+        }
+        if let Some(s5b) = self.s5b.as_mut(){
+            *s5b = S5B::new();
+        }
+    }
+
+    /// fits the chips named by a module's EXPANSION bitmask
+    pub fn set_expansion(&mut self, expansion: u32){
+        self.vrc6 = (expansion & 1 != 0).then(Vrc6::new);
+        self.vrc7 = (expansion & 2 != 0).then(Vrc7::new);
+        self.fds = (expansion & 4 != 0).then(Fds::new);
+        self.mmc5 = (expansion & 8 != 0).then(Mmc5::new);
+        self.n163 = (expansion & 16 != 0).then(|| N163::new(self.n163_mixing));
+        self.s5b = (expansion & 32 != 0).then(S5B::new);
+    }
+
+    /// the APU is reset with the region's tables, the expansion chips
+    /// simply run at its CPU clock
+    pub fn set_region(&mut self, region: Region){
+        self.region = region;
+        self.apu = Apu::new(region);
+    }
+
+    pub fn region(&self) -> Region{
+        self.region
+    }
+
+    /// kept across resets and expansion changes
+    pub fn set_n163_mixing(&mut self, mixing: N163Mixing){
+        self.n163_mixing = mixing;
+        if let Some(n163) = self.n163.as_mut(){
+            n163.set_mixing(mixing);
+        }
+    }
+
+    /// runs every chip for a single CPU cycle
+    pub fn clock(&mut self){
+        self.apu.clock();
+        if let Some(vrc6) = self.vrc6.as_mut(){
+            vrc6.clock();
+        }
+        if let Some(vrc7) = self.vrc7.as_mut(){
+            vrc7.clock();
+        }
+        if let Some(mmc5) = self.mmc5.as_mut(){
+            mmc5.clock();
+        }
+        if let Some(fds) = self.fds.as_mut(){
+            fds.clock();
+        }
+        if let Some(n163) = self.n163.as_mut(){
+            n163.clock();
+        }
+        if let Some(s5b) = self.s5b.as_mut(){
+            s5b.clock();
+        }
+    }
+
+    pub fn output(&self) -> f32{
+        let mut out = self.apu.output();
+        if let Some(vrc6) = self.vrc6.as_ref(){
+            out += vrc6.output() as f32 * VRC6_LEVEL;
+        }
+        if let Some(vrc7) = self.vrc7.as_ref(){
+            out += vrc7.output() * VRC7_LEVEL;
+        }
+        if let Some(mmc5) = self.mmc5.as_ref(){
+            out += mmc5.output();
+        }
+        if let Some(fds) = self.fds.as_ref(){
+            out += fds.output() * FDS_LEVEL;
+        }
+        if let Some(n163) = self.n163.as_ref(){
+            out += n163.output() * N163_LEVEL;
+        }
+        if let Some(s5b) = self.s5b.as_ref(){
+            out += s5b.output() * S5B_LEVEL;
+        }
+        out
+    }
+
+    pub fn set_dpcm_sample(&mut self, sample: Option<Arc<[u8]>>){
+        self.apu.set_dpcm_sample(sample);
+    }
+
+    /// writes to chips that aren't fitted, or to addresses no chip decodes,
+    /// are dropped, like on an open bus
+    pub fn write_register(&mut self, address: u16, value: u8){
+        match address{
+            0x4000..=0x4017 => self.apu.write_register(address, value),
+            0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002 => {
+                if let Some(vrc6) = self.vrc6.as_mut(){
+                    vrc6.write_register(address, value);
+                }
+            }
+            0x4040..=0x4080 | 0x4082..=0x408A => {
+                if let Some(fds) = self.fds.as_mut(){
+                    fds.write_register(address, value);
+                }
+            }
+            0x9010 | 0x9030 => {
+                if let Some(vrc7) = self.vrc7.as_mut(){
+                    vrc7.write_register(address, value);
+                }
+            }
+            0x4800 | 0xF800 => {
+                if let Some(n163) = self.n163.as_mut(){
+                    n163.write_register(address, value);
+                }
+            }
+            0x5000..=0x5007 | 0x5010 | 0x5011 | 0x5015 => {
+                if let Some(mmc5) = self.mmc5.as_mut(){
+                    mmc5.write_register(address, value);
+                }
+            }
+            0xC000 | 0xE000 => {
+                if let Some(s5b) = self.s5b.as_mut(){
+                    s5b.write_register(address, value);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for Board{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Apu{
+    pulse_0: Pulse,
+    pulse_1: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    mixer: Mixer,
+    cycle: u64,
+    region: Region,
 }
 
 impl Apu{
-    pub fn next_sample(&mut self) -> f32{
-        0.0
+    pub fn new(region: Region) -> Self{
+        Self {
+            pulse_0: Pulse::new(PulseChannel::One),
+            pulse_1: Pulse::new(PulseChannel::Two),
+            triangle: Triangle::new(),
+            noise: Noise::new(region),
+            dmc: Dmc::new(region),
+            frame_counter: FrameCounter::new(region),
+            mixer: Mixer::new(),
+            cycle: 0,
+            region,
+        }
     }
+
     pub fn reset(&mut self){
+        *self = Self::new(self.region);
+    }
 
+    /// runs the APU for a single CPU cycle
+    pub fn clock(&mut self){
+        if self.cycle & 1 == 1{
+            self.pulse_0.clock_timer();
+            self.pulse_1.clock_timer();
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        let result = self.frame_counter.clock();
+        self.handle_frame_result(result);
+
+        self.cycle += 1;
     }
-    pub fn new() -> Self{
-        Self { reg: [0; 15] }
+
+    fn handle_frame_result(&mut self, result: FrameResult){
+        match result{
+            FrameResult::None => {}
+            FrameResult::Quarter => self.clock_quarter_frame(),
+            FrameResult::Half => {
+                self.clock_quarter_frame();
+                self.pulse_0.clock_half_frame();
+                self.pulse_1.clock_half_frame();
+                self.triangle.clock_half_frame();
+                self.noise.clock_half_frame();
+            }
+        }
+    }
+
+    fn clock_quarter_frame(&mut self){
+        self.pulse_0.clock_quarter_frame();
+        self.pulse_1.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    /// true while either the frame counter or the DMC is asserting /IRQ
+    pub fn irq(&self) -> bool{
+        self.frame_counter.irq() || self.dmc.irq()
+    }
+
+    /// reads $4015, acknowledging the frame interrupt
+    pub fn read_status(&mut self) -> u8{
+        let mut status = 0;
+        status |= self.pulse_0.active() as u8;
+        status |= (self.pulse_1.active() as u8) << 1;
+        status |= (self.triangle.active() as u8) << 2;
+        status |= (self.noise.active() as u8) << 3;
+        status |= (self.dmc.active() as u8) << 4;
+        status |= (self.frame_counter.irq() as u8) << 6;
+        status |= (self.dmc.irq() as u8) << 7;
+        self.frame_counter.clear_irq();
+        status
+    }
+
+    pub fn output(&self) -> f32{
+        self.mixer.mix(
+            self.pulse_0.output(),
+            self.pulse_1.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        )
+    }
+
+    /// the sample the DMC reads from, $4012 offsets into it
+    pub fn set_dpcm_sample(&mut self, sample: Option<Arc<[u8]>>){
+        self.dmc.set_sample(sample);
     }
 
     pub fn write_register(&mut self, address: u16, value: u8) {
-        // match address {
-        //     0x4000..=0x4003 => self.pulse_0.write_register(address, value),
-        //     0x4004..=0x4007 => self.pulse_1.write_register(address, value),
-        //     0x4008..=0x400B => self.triangle.write_register(address, value),
-        //     0x400C..=0x400F => self.noise.write_register(address, value),
-        //     0x4010..=0x4013 => self.dmc.write_register(address, value),
-        //     0x4015 => {
-        //         self.pulse_0.set_enabled(value & 0b0000_0001 != 0);
-        //         self.pulse_1.set_enabled(value & 0b0000_0010 != 0);
-        //         self.triangle.set_enabled(value & 0b0000_0100 != 0);
-        //         self.noise.set_enabled(value & 0b0000_1000 != 0);
-        //         self.dmc.set_enabled(value & 0b0001_0000 != 0);
-        //     }
-        //     0x4017 => {
-        //         let r = self.frame_counter.write_register(value, cycles);
-        //         self.handle_frame_result(r);
-        //     }
-        //     _ => panic!("Bad APU address: {:04X}", address),
-        // }
-    }
-}
\ No newline at end of file
+        match address {
+            0x4000..=0x4003 => self.pulse_0.write_register(address - 0x4000, value),
+            0x4004..=0x4007 => self.pulse_1.write_register(address - 0x4004, value),
+            0x4008..=0x400B => self.triangle.write_register(address - 0x4008, value),
+            0x400C..=0x400F => self.noise.write_register(address - 0x400C, value),
+            0x4010..=0x4013 => self.dmc.write_register(address - 0x4010, value),
+            0x4015 => {
+                self.pulse_0.set_enabled(value & 0b0000_0001 != 0);
+                self.pulse_1.set_enabled(value & 0b0000_0010 != 0);
+                self.triangle.set_enabled(value & 0b0000_0100 != 0);
+                self.noise.set_enabled(value & 0b0000_1000 != 0);
+                self.dmc.set_enabled(value & 0b0001_0000 != 0);
+            }
+            0x4017 => {
+                let r = self.frame_counter.write_register(value, self.cycle);
+                self.handle_frame_result(r);
+            }
+            // $4014 and $4016 belong to the CPU's DMA and controller ports
+            _ => {}
+        }
+    }
+}
+
+impl Default for Apu{
+    fn default() -> Self {
+        Self::new(Region::default())
+    }
+}
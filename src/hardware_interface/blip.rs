@@ -0,0 +1,102 @@
+use std::f64::consts::PI;
+
+const PHASES: usize = 64;
+const HALF_WIDTH: usize = 16;
+const WIDTH: usize = HALF_WIDTH * 2;
+/// cutoff of the step kernel as a fraction of the output sample rate
+const CUTOFF: f64 = 0.45;
+
+/// Band-limited step buffer in the style of blip_buf.
+///
+/// Amplitude changes are added as deltas at the clock they happen on, each
+/// one spread over the output samples by a windowed sinc kernel. Reading
+/// integrates the deltas back into a waveform free of the aliasing that
+/// point sampling a square wave produces.
+#[derive(Debug)]
+pub struct BlipBuf{
+    /// output samples per input clock
+    ratio: f64,
+    /// position of the current frame's start in output samples
+    offset: f64,
+    kernel: Vec<[f32; WIDTH]>,
+    buf: Vec<f32>,
+    integrator: f32,
+}
+
+impl BlipBuf{
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self{
+        Self {
+            ratio: sample_rate / clock_rate,
+            offset: 0.0,
+            kernel: Self::build_kernel(),
+            buf: vec![0.0; WIDTH],
+            integrator: 0.0,
+        }
+    }
+
+    /// changes the input clock, deltas already added keep their place
+    pub fn set_clock_rate(&mut self, clock_rate: f64, sample_rate: f64){
+        self.ratio = sample_rate / clock_rate;
+    }
+
+    /// one impulse per sub-sample phase, plus a final one for interpolation
+    fn build_kernel() -> Vec<[f32; WIDTH]>{
+        (0..=PHASES).map(|phase| {
+            let frac = phase as f64 / PHASES as f64;
+            let mut taps = [0.0f64; WIDTH];
+            for (k, tap) in taps.iter_mut().enumerate(){
+                let t = k as f64 - (HALF_WIDTH - 1) as f64 - frac;
+                let x = 2.0 * CUTOFF * t;
+                let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                let window = 0.42
+                    + 0.5 * (PI * t / HALF_WIDTH as f64).cos()
+                    + 0.08 * (2.0 * PI * t / HALF_WIDTH as f64).cos();
+                *tap = sinc * window.max(0.0);
+            }
+            let sum: f64 = taps.iter().sum();
+            taps.map(|tap| (tap / sum) as f32)
+        }).collect()
+    }
+
+    /// adds an amplitude change `time` clocks after the start of the frame
+    pub fn add_delta(&mut self, time: u32, delta: f32){
+        let pos = self.offset + time as f64 * self.ratio;
+        let index = pos as usize;
+        let frac = (pos - index as f64) * PHASES as f64;
+        let phase = frac as usize;
+        let interp = (frac - phase as f64) as f32;
+
+        if self.buf.len() < index + WIDTH{
+            self.buf.resize(index + WIDTH, 0.0);
+        }
+        let (a, b) = (&self.kernel[phase], &self.kernel[phase + 1]);
+        for (k, out) in self.buf[index..index + WIDTH].iter_mut().enumerate(){
+            *out += delta * (a[k] + (b[k] - a[k]) * interp);
+        }
+    }
+
+    /// ends the current frame after `clocks`, making its samples readable
+    pub fn end_frame(&mut self, clocks: u32){
+        self.offset += clocks as f64 * self.ratio;
+        let needed = self.offset as usize + WIDTH;
+        if self.buf.len() < needed{
+            self.buf.resize(needed, 0.0);
+        }
+    }
+
+    pub fn samples_avail(&self) -> usize{
+        self.offset as usize
+    }
+
+    /// reads up to `out.len()` finished samples, returning how many were read
+    pub fn read_samples(&mut self, out: &mut [f32]) -> usize{
+        let count = out.len().min(self.samples_avail());
+        for (sample, delta) in out.iter_mut().zip(&self.buf[..count]){
+            self.integrator += delta;
+            *sample = self.integrator;
+        }
+        self.buf.drain(..count);
+        self.offset -= count as f64;
+        count
+    }
+}
@@ -0,0 +1,264 @@
+use std::sync::Arc;
+
+use super::Region;
+
+/// timer periods in CPU cycles
+const DMC_RATES_NTSC: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const DMC_RATES_PAL: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// FamiTracker pads samples up to their register length with this byte,
+/// its alternating bits keep the delta counter where it is
+const PADDING_BYTE: u8 = 0xAA;
+
+bitfield!{
+    struct Control(u8);
+    u8;
+    irq_enabled, _: 7;
+    loop_flag, _: 6;
+    rate, _: 3, 0;
+}
+
+/// Delta modulation channel.
+///
+/// Instead of fetching from a CPU address space the memory reader walks the
+/// sample it was given with [`Dmc::set_sample`]. $4012 is an offset into
+/// that sample in 64 byte units, the same way FamiTracker's Yxx works.
+#[derive(Debug)]
+pub struct Dmc{
+    rates: &'static [u16; 16],
+    sample: Option<Arc<[u8]>>,
+
+    irq_enabled: bool,
+    loop_flag: bool,
+    timer_period: u16,
+    timer: u16,
+    sample_offset: usize,
+    sample_length: usize,
+
+    current_offset: usize,
+    bytes_remaining: usize,
+    sample_buffer: Option<u8>,
+
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+    level: u8,
+
+    irq: bool,
+}
+
+impl Dmc{
+    pub fn new(region: Region) -> Self{
+        let rates = match region{
+            Region::Ntsc | Region::Dendy => &DMC_RATES_NTSC,
+            Region::Pal => &DMC_RATES_PAL,
+        };
+        Self {
+            rates,
+            sample: None,
+            irq_enabled: false,
+            loop_flag: false,
+            timer_period: rates[0],
+            timer: 0,
+            sample_offset: 0,
+            sample_length: 1,
+            current_offset: 0,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            level: 0,
+            irq: false,
+        }
+    }
+
+    pub fn set_sample(&mut self, sample: Option<Arc<[u8]>>){
+        self.sample = sample;
+    }
+
+    /// `register` is the offset from $4010 (0-3)
+    pub fn write_register(&mut self, register: u16, value: u8){
+        match register{
+            0 => {
+                let reg = Control(value);
+                self.irq_enabled = reg.irq_enabled();
+                if !self.irq_enabled{
+                    self.irq = false;
+                }
+                self.loop_flag = reg.loop_flag();
+                self.timer_period = self.rates[reg.rate() as usize];
+            }
+            1 => self.level = value & 0x7F,
+            2 => self.sample_offset = value as usize * 64,
+            3 => self.sample_length = value as usize * 16 + 1,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool){
+        self.irq = false;
+        if !enabled{
+            self.bytes_remaining = 0;
+        }else if self.bytes_remaining == 0{
+            self.restart();
+            self.fill_buffer();
+        }
+    }
+
+    pub fn active(&self) -> bool{
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq(&self) -> bool{
+        self.irq
+    }
+
+    fn restart(&mut self){
+        self.current_offset = self.sample_offset;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn read_sample_byte(&self, offset: usize) -> u8{
+        self.sample
+            .as_ref()
+            .and_then(|sample| sample.get(offset).copied())
+            .unwrap_or(PADDING_BYTE)
+    }
+
+    fn fill_buffer(&mut self){
+        if self.sample_buffer.is_some() || self.bytes_remaining == 0{
+            return;
+        }
+        self.sample_buffer = Some(self.read_sample_byte(self.current_offset));
+        self.current_offset += 1;
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0{
+            if self.loop_flag{
+                self.restart();
+            }else if self.irq_enabled{
+                self.irq = true;
+            }
+        }
+    }
+
+    /// clocked once every CPU cycle, the rate table is in CPU cycles
+    pub fn clock_timer(&mut self){
+        if self.timer > 0{
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence{
+            if self.shift & 1 == 1{
+                if self.level <= 125{
+                    self.level += 2;
+                }
+            }else if self.level >= 2{
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0{
+            self.bits_remaining = 8;
+            match self.sample_buffer.take(){
+                Some(byte) => {
+                    self.silence = false;
+                    self.shift = byte;
+                    self.fill_buffer();
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8{
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// a DMC playing a sample of 64 falling bytes then 64 rising ones from
+    /// a DPCMDEF, at the fastest rate
+    fn ramp(control: u8, offset: u8, length: u8) -> Dmc{
+        // the last DPCM line needs something after its newline
+        let text = format!("DPCMDEF   0   128 \"Ramp\"\n\
+            DPCM : {}\n\
+            DPCM : {}\n\
+            # end\n", vec!["00"; 64].join(" "), vec!["FF"; 64].join(" "));
+        let file = crate::parser::read_text(&text).unwrap();
+        let mut dmc = Dmc::new(Region::Ntsc);
+        dmc.set_sample(Some(file.dpcmdef[0].data.clone()));
+        dmc.write_register(0, control | 0x0F);
+        dmc.write_register(1, 0x40);
+        dmc.write_register(2, offset);
+        dmc.write_register(3, length);
+        dmc.set_enabled(true);
+        dmc
+    }
+
+    /// CPU cycles until the last byte is fetched, and whether the level
+    /// went up once it has played
+    fn play(mut dmc: Dmc) -> (u32, bool){
+        let mut cycles = 0;
+        while dmc.bytes_remaining > 0{
+            dmc.clock_timer();
+            cycles += 1;
+        }
+        for _ in 0..2 * 8 * 54{
+            dmc.clock_timer();
+        }
+        (cycles, dmc.output() > 0x40)
+    }
+
+    #[test]
+    pub fn registers_pick_the_bytes(){
+        // $4012 skips 64 bytes at a time
+        assert!(!play(ramp(0x00, 0, 0)).1);
+        assert!(play(ramp(0x00, 1, 0)).1);
+        // $4013 plays 16 bytes more at a time, a byte is 8 bits of 54 cycles
+        let (one, _) = play(ramp(0x00, 0, 0));
+        let (seventeen, _) = play(ramp(0x00, 0, 1));
+        let (thirty_three, _) = play(ramp(0x00, 0, 2));
+        assert_eq!(one, 0);
+        assert_eq!(thirty_three - seventeen, 16 * 8 * 54);
+
+        // a looping sample starts over instead of ending
+        let mut dmc = ramp(0x40, 0, 1);
+        for _ in 0..100_000{
+            dmc.clock_timer();
+            assert!(dmc.bytes_remaining > 0);
+        }
+    }
+
+    #[test]
+    pub fn irq_with_the_last_fetch(){
+        let (seventeen, _) = play(ramp(0x00, 0, 1));
+        let mut dmc = ramp(0x80, 0, 1);
+        let mut cycles = 0;
+        while !dmc.irq(){
+            assert!(dmc.active());
+            dmc.clock_timer();
+            cycles += 1;
+        }
+        assert_eq!(cycles, seventeen);
+        assert!(!dmc.active());
+
+        // never while looping
+        let mut dmc = ramp(0xC0, 0, 1);
+        for _ in 0..100_000{
+            dmc.clock_timer();
+            assert!(!dmc.irq());
+        }
+    }
+}
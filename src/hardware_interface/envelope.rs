@@ -0,0 +1,51 @@
+/// Volume envelope shared by the pulse and noise channels.
+///
+/// Either outputs the constant volume from the control register or a
+/// sawtooth that decays from 15 to 0 once per divider period.
+#[derive(Default, Debug)]
+pub struct Envelope{
+    start: bool,
+    loop_flag: bool,
+    constant: bool,
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope{
+    pub fn write_control(&mut self, loop_flag: bool, constant: bool, volume: u8){
+        self.loop_flag = loop_flag;
+        self.constant = constant;
+        self.volume = volume & 0x0F;
+    }
+
+    pub fn restart(&mut self){
+        self.start = true;
+    }
+
+    /// clocked by the frame counter on every quarter frame
+    pub fn clock(&mut self){
+        if self.start{
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        }else if self.divider == 0{
+            self.divider = self.volume;
+            if self.decay > 0{
+                self.decay -= 1;
+            }else if self.loop_flag{
+                self.decay = 15;
+            }
+        }else{
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8{
+        if self.constant{
+            self.volume
+        }else{
+            self.decay
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::f32::consts::PI;
+
+use super::CPU_CLOCK_NTSC;
+
+/// change of the modulation counter for each 3 bit table entry, `None`
+/// resets it
+const MOD_STEPS: [Option<i8>; 8] = [Some(0), Some(1), Some(2), Some(4), None, Some(-4), Some(-2), Some(-1)];
+/// the master volume of $4089, 2/2, 2/3, 2/4 and 2/5 of full scale
+const MASTER_VOLUME: [f32; 4] = [1.0, 2.0 / 3.0, 2.0 / 4.0, 2.0 / 5.0];
+/// gains above this are allowed but output no louder
+const MAX_GAIN: u8 = 32;
+/// the RC low pass between the FDS DAC and the cartridge output
+const LOW_PASS_CUTOFF: f32 = 2000.0;
+
+bitfield!{
+    struct EnvelopeControl(u8);
+    u8;
+    disabled, _: 7;
+    increase, _: 6;
+    speed, _: 5, 0;
+}
+
+/// Shared by the volume envelope ($4080) and the modulation envelope
+/// ($4084), disabled it just holds the gain written to it.
+#[derive(Debug, Default)]
+struct Envelope{
+    disabled: bool,
+    increase: bool,
+    speed: u8,
+    gain: u8,
+    divider: u32,
+}
+
+impl Envelope{
+    fn write_register(&mut self, value: u8){
+        let reg = EnvelopeControl(value);
+        self.disabled = reg.disabled();
+        self.increase = reg.increase();
+        self.speed = reg.speed();
+        self.divider = 0;
+        if self.disabled{
+            self.gain = self.speed;
+        }
+    }
+
+    /// `master` is $408A, the envelope steps every 8 * (master + 1) *
+    /// (speed + 1) cycles
+    fn clock(&mut self, master: u8){
+        if self.disabled{
+            return;
+        }
+        self.divider += 1;
+        if self.divider < 8 * (master as u32 + 1) * (self.speed as u32 + 1){
+            return;
+        }
+        self.divider = 0;
+        if self.increase{
+            self.gain = (self.gain + 1).min(MAX_GAIN);
+        }else{
+            self.gain = self.gain.saturating_sub(1);
+        }
+    }
+}
+
+/// The Famicom Disk System's sound unit: one 64 step wavetable voice with
+/// a volume envelope and a frequency modulator.
+#[derive(Debug)]
+pub struct Fds{
+    wave: [u8; 64],
+    /// $4089 bit 7, the wave can be written and the output holds
+    wave_write: bool,
+    master_volume: u8,
+    pitch: u16,
+    wave_halt: bool,
+    envelope_halt: bool,
+    /// 6 bits of wave position above 16 bits of fraction
+    wave_accumulator: u32,
+    volume: Envelope,
+    envelope_speed: u8,
+
+    mod_table: [u8; 64],
+    mod_position: u8,
+    mod_pitch: u16,
+    mod_halt: bool,
+    mod_accumulator: u32,
+    /// 7 bit signed, bent by the table and scaled by the mod envelope
+    mod_counter: i8,
+    modulation: Envelope,
+
+    output: u16,
+    filtered: f32,
+    filter_alpha: f32,
+}
+
+impl Fds{
+    pub fn new() -> Self{
+        let rc = 1.0 / (2.0 * PI * LOW_PASS_CUTOFF);
+        let dt = 1.0 / CPU_CLOCK_NTSC as f32;
+        Self {
+            wave: [0; 64],
+            wave_write: false,
+            master_volume: 0,
+            pitch: 0,
+            wave_halt: true,
+            envelope_halt: true,
+            wave_accumulator: 0,
+            volume: Default::default(),
+            envelope_speed: 0xE8,
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_pitch: 0,
+            mod_halt: true,
+            mod_accumulator: 0,
+            mod_counter: 0,
+            modulation: Default::default(),
+            output: 0,
+            filtered: 0.0,
+            filter_alpha: dt / (rc + dt),
+        }
+    }
+
+    /// runs the chip for a single CPU cycle
+    pub fn clock(&mut self){
+        if !self.wave_halt && !self.envelope_halt && self.envelope_speed > 0{
+            self.volume.clock(self.envelope_speed);
+            self.modulation.clock(self.envelope_speed);
+        }
+
+        if !self.mod_halt{
+            self.mod_accumulator += self.mod_pitch as u32;
+            if self.mod_accumulator >= 0x10000{
+                self.mod_accumulator -= 0x10000;
+                self.step_modulator();
+            }
+        }
+
+        if !self.wave_halt{
+            let pitch = self.modulated_pitch();
+            self.wave_accumulator = (self.wave_accumulator + pitch) & 0x3F_FFFF;
+        }
+        if !self.wave_write{
+            let gain = self.volume.gain.min(MAX_GAIN);
+            self.output = self.wave[(self.wave_accumulator >> 16) as usize] as u16 * gain as u16;
+        }
+        self.filtered += (self.output as f32 - self.filtered) * self.filter_alpha;
+    }
+
+    fn step_modulator(&mut self){
+        let entry = self.mod_table[self.mod_position as usize];
+        self.mod_counter = match MOD_STEPS[entry as usize]{
+            // the counter wraps at 7 bits
+            Some(step) => ((((self.mod_counter + step) as u8) << 1) as i8) >> 1,
+            None => 0,
+        };
+        self.mod_position = (self.mod_position + 1) & 0x3F;
+    }
+
+    /// the wave pitch bent by the modulator, as the nesdev wiki describes
+    /// the hardware's rounding
+    fn modulated_pitch(&self) -> u32{
+        let mut temp = self.mod_counter as i32 * self.modulation.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0{
+            temp += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192{
+            temp -= 256;
+        }else if temp < -64{
+            temp += 256;
+        }
+        let mut temp = self.pitch as i32 * temp;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32{
+            temp += 1;
+        }
+        (self.pitch as i32 + temp).max(0) as u32
+    }
+
+    /// the filtered DAC, 0.0 to 1.0 at full gain and master volume
+    pub fn output(&self) -> f32{
+        self.filtered * MASTER_VOLUME[self.master_volume as usize] / (63 * MAX_GAIN as u16) as f32
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8){
+        match address{
+            0x4040..=0x407F if self.wave_write => {
+                self.wave[(address - 0x4040) as usize] = value & 0x3F;
+            }
+            0x4080 => self.volume.write_register(value),
+            0x4082 => self.pitch = (self.pitch & 0x0F00) | value as u16,
+            0x4083 => {
+                self.pitch = (self.pitch & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.wave_halt = value & 0x80 != 0;
+                self.envelope_halt = value & 0x40 != 0;
+                if self.wave_halt{
+                    self.wave_accumulator = 0;
+                }
+            }
+            0x4084 => self.modulation.write_register(value),
+            0x4085 => self.mod_counter = (((value & 0x7F) << 1) as i8) >> 1,
+            0x4086 => self.mod_pitch = (self.mod_pitch & 0x0F00) | value as u16,
+            0x4087 => {
+                self.mod_pitch = (self.mod_pitch & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.mod_halt = value & 0x80 != 0;
+                if self.mod_halt{
+                    self.mod_accumulator = 0;
+                    self.mod_position = 0;
+                }
+            }
+            // each write fills two entries, so 32 writes cover the table
+            0x4088 if self.mod_halt => {
+                self.mod_table[self.mod_position as usize] = value & 0x07;
+                self.mod_table[self.mod_position as usize + 1] = value & 0x07;
+                self.mod_position = (self.mod_position + 2) & 0x3F;
+            }
+            0x4089 => {
+                self.wave_write = value & 0x80 != 0;
+                self.master_volume = value & 0x03;
+            }
+            0x408A => self.envelope_speed = value,
+            // addresses the chip doesn't decode are ignored
+            _ => {}
+        }
+    }
+}
+
+impl Default for Fds{
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,224 @@
+use super::Region;
+
+/// CPU cycles at which each step of the sequencer fires, the last entry is
+/// the length of the whole sequence
+const STEPS_4_NTSC: [u32; 5] = [7457, 14913, 22371, 29829, 29830];
+const STEPS_5_NTSC: [u32; 6] = [7457, 14913, 22371, 29829, 37281, 37282];
+const STEPS_4_PAL: [u32; 5] = [8313, 16627, 24939, 33253, 33254];
+const STEPS_5_PAL: [u32; 6] = [8313, 16627, 24939, 33253, 41565, 41566];
+
+bitfield!{
+    struct Control(u8);
+    u8;
+    five_step, _: 7;
+    irq_inhibit, _: 6;
+}
+
+/// Which units the frame counter clocked this cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameResult{
+    None,
+    /// envelopes and the triangle's linear counter
+    Quarter,
+    /// everything clocked by a quarter frame plus length counters and sweeps
+    Half,
+}
+
+#[derive(Debug)]
+pub struct FrameCounter{
+    steps_4: &'static [u32; 5],
+    steps_5: &'static [u32; 6],
+    five_step: bool,
+    irq_inhibit: bool,
+    irq: bool,
+    cycle: u32,
+    /// CPU cycles until a $4017 write resets the sequencer
+    pending_reset: Option<u8>,
+}
+
+impl FrameCounter{
+    pub fn new(region: Region) -> Self{
+        let (steps_4, steps_5) = match region{
+            Region::Ntsc | Region::Dendy => (&STEPS_4_NTSC, &STEPS_5_NTSC),
+            Region::Pal => (&STEPS_4_PAL, &STEPS_5_PAL),
+        };
+        Self {
+            steps_4,
+            steps_5,
+            five_step: false,
+            irq_inhibit: false,
+            irq: false,
+            cycle: 0,
+            pending_reset: None,
+        }
+    }
+
+    /// `cycles` is the CPU cycle the write happens on, the reset lands 3
+    /// cycles later when written on an APU cycle and 4 cycles later otherwise
+    pub fn write_register(&mut self, value: u8, cycles: u64) -> FrameResult{
+        let reg = Control(value);
+        self.five_step = reg.five_step();
+        self.irq_inhibit = reg.irq_inhibit();
+        if self.irq_inhibit{
+            self.irq = false;
+        }
+        self.pending_reset = Some(if cycles & 1 == 1 { 3 } else { 4 });
+
+        // 5 step mode clocks everything straight away
+        if self.five_step{
+            FrameResult::Half
+        }else{
+            FrameResult::None
+        }
+    }
+
+    pub fn clock(&mut self) -> FrameResult{
+        if let Some(delay) = self.pending_reset{
+            if delay == 0{
+                self.pending_reset = None;
+                self.cycle = 0;
+            }else{
+                self.pending_reset = Some(delay - 1);
+            }
+        }
+
+        self.cycle += 1;
+        let result = if self.five_step{
+            let steps = self.steps_5;
+            if self.cycle == steps[0] || self.cycle == steps[2]{
+                FrameResult::Quarter
+            }else if self.cycle == steps[1] || self.cycle == steps[4]{
+                FrameResult::Half
+            }else{
+                FrameResult::None
+            }
+        }else{
+            let steps = self.steps_4;
+            if self.cycle >= steps[3] - 1 && !self.irq_inhibit{
+                self.irq = true;
+            }
+            if self.cycle == steps[0] || self.cycle == steps[2]{
+                FrameResult::Quarter
+            }else if self.cycle == steps[1] || self.cycle == steps[3]{
+                FrameResult::Half
+            }else{
+                FrameResult::None
+            }
+        };
+
+        let length = if self.five_step { self.steps_5[5] } else { self.steps_4[4] };
+        if self.cycle >= length{
+            self.cycle = 0;
+        }
+        result
+    }
+
+    pub fn irq(&self) -> bool{
+        self.irq
+    }
+
+    /// reading $4015 acknowledges the frame interrupt
+    pub fn clear_irq(&mut self){
+        self.irq = false;
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::hardware_interface::{Apu, Region};
+
+    /// clocks `apu` until `done`, returning how many cycles that took
+    fn clock_until(apu: &mut Apu, mut done: impl FnMut(&mut Apu) -> bool) -> u32{
+        let mut cycles = 0;
+        while !done(apu){
+            apu.clock();
+            cycles += 1;
+            assert!(cycles < 100_000, "never happened");
+        }
+        cycles
+    }
+
+    #[test]
+    pub fn steps_land_on_their_cycles(){
+        // a length of 2 runs out on the second half frame
+        let mut apu = Apu::new(Region::Ntsc);
+        apu.write_register(0x4015, 0x01);
+        apu.write_register(0x4003, 0x18);
+        assert_eq!(clock_until(&mut apu, |apu| apu.read_status() & 0x01 == 0), 29829);
+
+        // in 5 step mode the half frames are on 14913 and 37281, counted
+        // from 4 cycles after a $4017 write on an even cycle
+        let mut apu = Apu::new(Region::Ntsc);
+        apu.write_register(0x4017, 0x80);
+        apu.write_register(0x4015, 0x01);
+        apu.write_register(0x4003, 0x18);
+        assert_eq!(clock_until(&mut apu, |apu| apu.read_status() & 0x01 == 0), 4 + 37281);
+        // the write itself clocks a half frame, and lands 3 cycles later on
+        // an odd cycle
+        apu.write_register(0x4003, 0x18);
+        apu.write_register(0x4017, 0x80);
+        assert_eq!(clock_until(&mut apu, |apu| apu.read_status() & 0x01 == 0), 3 + 14913);
+        assert!(!apu.irq(), "5 step mode never interrupts");
+
+        // the envelope only moves on quarter frames, its level halfway
+        // through each step of the sequence
+        let levels = |control| {
+            let mut apu = Apu::new(Region::Ntsc);
+            apu.write_register(0x4017, control);
+            apu.write_register(0x4015, 0x01);
+            apu.write_register(0x4000, 0xA0);
+            apu.write_register(0x4002, 0x40);
+            apu.write_register(0x4003, 0x08);
+            let out: Vec<f32> = (0..34_560).map(|_| { apu.clock(); apu.output() }).collect();
+            // over a whole period of the square
+            [3700, 11000, 18600, 26000, 33500].map(|middle| out[middle..middle + 1040].iter().copied().fold(0.0, f32::max))
+        };
+        let four = levels(0x00);
+        assert!(four[0] < four[1], "the envelope starts on the first quarter frame");
+        assert!(four.windows(2).skip(1).all(|pair| pair[0] > pair[1]));
+        let five = levels(0x80);
+        assert!(five[1] > five[2] && five[2] > five[3]);
+        assert_eq!(five[3], five[4], "the 4th step clocks nothing");
+    }
+
+    #[test]
+    pub fn frame_irq(){
+        let mut apu = Apu::new(Region::Ntsc);
+        assert_eq!(clock_until(&mut apu, |apu| apu.irq()), 29828);
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.irq(), "reading $4015 acknowledges it");
+        // the flag is raised again on each of the last 3 cycles
+        apu.clock();
+        assert!(apu.irq());
+        apu.clock();
+        apu.clock();
+        apu.read_status();
+        apu.clock();
+        assert!(!apu.irq());
+        assert_eq!(apu.read_status() & 0x40, 0);
+
+        // the inhibit bit clears the flag and keeps it down, 2 cycles into
+        // the next frame by now
+        assert_eq!(clock_until(&mut apu, |apu| apu.irq()), 29826);
+        apu.write_register(0x4017, 0x40);
+        assert!(!apu.irq());
+        for _ in 0..2 * 29830{
+            apu.clock();
+            assert!(!apu.irq());
+        }
+    }
+
+    #[test]
+    pub fn dmc_irq_in_status(){
+        // a one byte sample with its IRQ on finishes as soon as it's fetched
+        let mut apu = Apu::new(Region::Ntsc);
+        apu.write_register(0x4010, 0x80);
+        apu.write_register(0x4013, 0x00);
+        apu.write_register(0x4015, 0x10);
+        assert_eq!(apu.read_status() & 0x80, 0x80);
+        assert_eq!(apu.read_status() & 0x80, 0x80, "reading $4015 leaves it");
+        apu.write_register(0x4015, 0x00);
+        assert_eq!(apu.read_status() & 0x80, 0, "writing $4015 clears it");
+        assert!(!apu.irq());
+    }
+}
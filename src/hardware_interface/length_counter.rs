@@ -0,0 +1,44 @@
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Silences a channel after a number of half frames unless halted.
+#[derive(Default, Debug)]
+pub struct LengthCounter{
+    enabled: bool,
+    halt: bool,
+    counter: u8,
+}
+
+impl LengthCounter{
+    /// mirrors the channel's bit in $4015, disabling clears the counter
+    pub fn set_enabled(&mut self, enabled: bool){
+        self.enabled = enabled;
+        if !enabled{
+            self.counter = 0;
+        }
+    }
+
+    pub fn set_halt(&mut self, halt: bool){
+        self.halt = halt;
+    }
+
+    /// loads the counter from the top five bits of the channel's last register
+    pub fn load(&mut self, index: u8){
+        if self.enabled{
+            self.counter = LENGTH_TABLE[(index & 0x1F) as usize];
+        }
+    }
+
+    /// clocked by the frame counter on every half frame
+    pub fn clock(&mut self){
+        if !self.halt && self.counter > 0{
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool{
+        self.counter > 0
+    }
+}
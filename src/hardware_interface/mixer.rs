@@ -0,0 +1,139 @@
+use std::f32::consts::PI;
+
+/// The 2A03's non-linear DAC, approximated with the lookup tables from
+/// the nesdev wiki.
+#[derive(Debug)]
+pub struct Mixer{
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+}
+
+impl Mixer{
+    pub fn new() -> Self{
+        let mut pulse_table = [0.0; 31];
+        for (n, out) in pulse_table.iter_mut().enumerate().skip(1){
+            *out = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        let mut tnd_table = [0.0; 203];
+        for (n, out) in tnd_table.iter_mut().enumerate().skip(1){
+            *out = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        Self { pulse_table, tnd_table }
+    }
+
+    pub fn mix(&self, pulse_0: u8, pulse_1: u8, triangle: u8, noise: u8, dmc: u8) -> f32{
+        self.pulse(pulse_0, pulse_1) + self.tnd(triangle, noise, dmc)
+    }
+
+    pub fn pulse(&self, pulse_0: u8, pulse_1: u8) -> f32{
+        self.pulse_table[(pulse_0 + pulse_1) as usize]
+    }
+
+    pub fn tnd(&self, triangle: u8, noise: u8, dmc: u8) -> f32{
+        self.tnd_table[3 * triangle as usize + 2 * noise as usize + dmc as usize]
+    }
+}
+
+impl Default for Mixer{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Which stages of the console's output filter chain are applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilterSettings{
+    pub high_pass_90: bool,
+    pub high_pass_440: bool,
+    pub low_pass_14k: bool,
+}
+
+impl Default for FilterSettings{
+    /// everything enabled, like a stock front loader
+    fn default() -> Self {
+        Self {
+            high_pass_90: true,
+            high_pass_440: true,
+            low_pass_14k: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FilterKind{
+    HighPass,
+    LowPass,
+}
+
+/// first order RC filter
+#[derive(Debug)]
+struct Filter{
+    kind: FilterKind,
+    alpha: f32,
+    prev_in: f32,
+    prev_out: f32,
+}
+
+impl Filter{
+    fn new(kind: FilterKind, cutoff: f32, sample_rate: f32) -> Self{
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        let alpha = match kind{
+            FilterKind::HighPass => rc / (rc + dt),
+            FilterKind::LowPass => dt / (rc + dt),
+        };
+        Self { kind, alpha, prev_in: 0.0, prev_out: 0.0 }
+    }
+
+    fn process(&mut self, input: f32) -> f32{
+        let out = match self.kind{
+            FilterKind::HighPass => self.alpha * (self.prev_out + input - self.prev_in),
+            FilterKind::LowPass => self.prev_out + self.alpha * (input - self.prev_out),
+        };
+        self.prev_in = input;
+        self.prev_out = out;
+        out
+    }
+}
+
+/// The 90 Hz and 440 Hz high-pass and 14 kHz low-pass filters between the
+/// mixer and the audio output, each can be switched off on its own.
+#[derive(Debug)]
+pub struct FilterChain{
+    settings: FilterSettings,
+    high_pass_90: Filter,
+    high_pass_440: Filter,
+    low_pass_14k: Filter,
+}
+
+impl FilterChain{
+    pub fn new(sample_rate: f32) -> Self{
+        Self {
+            settings: Default::default(),
+            high_pass_90: Filter::new(FilterKind::HighPass, 90.0, sample_rate),
+            high_pass_440: Filter::new(FilterKind::HighPass, 440.0, sample_rate),
+            low_pass_14k: Filter::new(FilterKind::LowPass, 14000.0, sample_rate),
+        }
+    }
+
+    pub fn settings(&self) -> FilterSettings{
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: FilterSettings){
+        self.settings = settings;
+    }
+
+    pub fn process(&mut self, mut sample: f32) -> f32{
+        if self.settings.high_pass_90{
+            sample = self.high_pass_90.process(sample);
+        }
+        if self.settings.high_pass_440{
+            sample = self.high_pass_440.process(sample);
+        }
+        if self.settings.low_pass_14k{
+            sample = self.low_pass_14k.process(sample);
+        }
+        sample
+    }
+}
@@ -0,0 +1,85 @@
+use super::{pulse::{Pulse, PulseChannel}, mixer::Mixer};
+
+/// CPU cycles between clocks of the MMC5's own 240 Hz frame timer
+const FRAME_PERIOD: u32 = 7457;
+
+bitfield!{
+    struct PcmControl(u8);
+    u8;
+    read_mode, _: 0;
+}
+
+/// Nintendo's MMC5 expansion audio: two 2A03 pulses without sweep units
+/// and a raw 8 bit PCM channel.
+#[derive(Debug)]
+pub struct Mmc5{
+    pulse_0: Pulse,
+    pulse_1: Pulse,
+    /// in read mode the PCM level comes from CPU reads, which nothing here
+    /// makes, so $5011 writes are ignored
+    pcm_read_mode: bool,
+    pcm: u8,
+    frame_divider: u32,
+    mixer: Mixer,
+    cycle: u64,
+}
+
+impl Mmc5{
+    pub fn new() -> Self{
+        Self {
+            pulse_0: Pulse::new(PulseChannel::Mmc5),
+            pulse_1: Pulse::new(PulseChannel::Mmc5),
+            pcm_read_mode: false,
+            pcm: 0,
+            frame_divider: FRAME_PERIOD,
+            mixer: Mixer::new(),
+            cycle: 0,
+        }
+    }
+
+    /// runs the chip for a single CPU cycle, the frame timer clocks
+    /// envelopes and length counters together
+    pub fn clock(&mut self){
+        if self.cycle & 1 == 1{
+            self.pulse_0.clock_timer();
+            self.pulse_1.clock_timer();
+        }
+        self.frame_divider -= 1;
+        if self.frame_divider == 0{
+            self.frame_divider = FRAME_PERIOD;
+            for pulse in [&mut self.pulse_0, &mut self.pulse_1]{
+                pulse.clock_quarter_frame();
+                pulse.clock_half_frame();
+            }
+        }
+        self.cycle += 1;
+    }
+
+    /// the pulses go through the 2A03's pulse DAC, the PCM is as loud as
+    /// the DMC at the same level
+    pub fn output(&self) -> f32{
+        self.mixer.pulse(self.pulse_0.output(), self.pulse_1.output()) + self.mixer.tnd(0, 0, self.pcm >> 1)
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8){
+        match address{
+            0x5000..=0x5003 => self.pulse_0.write_register(address - 0x5000, value),
+            0x5004..=0x5007 => self.pulse_1.write_register(address - 0x5004, value),
+            0x5010 => self.pcm_read_mode = PcmControl(value).read_mode(),
+            // a zero write would raise an IRQ instead of changing the level
+            0x5011 if !self.pcm_read_mode && value != 0 => self.pcm = value,
+            0x5015 => {
+                self.pulse_0.set_enabled(value & 0b01 != 0);
+                self.pulse_1.set_enabled(value & 0b10 != 0);
+            }
+            // addresses the chip doesn't decode are ignored
+            _ => {}
+        }
+    }
+}
+
+impl Default for Mmc5{
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,132 @@
+/// CPU cycles spent updating each channel
+const CHANNEL_CYCLES: u8 = 15;
+
+bitfield!{
+    struct AddressPort(u8);
+    u8;
+    auto_increment, _: 7;
+    address, _: 6, 0;
+}
+
+/// How the N163's channels reach its single DAC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum N163Mixing{
+    /// the DAC plays each channel in turn, with more channels the switching
+    /// rate falls into the audible range as hiss
+    #[default]
+    Multiplexed,
+    /// every channel's latest sample is averaged, as FamiTracker can do
+    HissFree,
+}
+
+/// Namco's 163 expansion audio: up to eight wavetable channels sharing
+/// 128 bytes of RAM with their own registers, one channel updated at a time.
+#[derive(Debug)]
+pub struct N163{
+    ram: [u8; 128],
+    address: u8,
+    auto_increment: bool,
+    mixing: N163Mixing,
+    divider: u8,
+    /// RAM offset of the registers of the channel updated next
+    current: u8,
+    /// each channel's last sample times its volume, indexed like `current`
+    outputs: [i16; 8],
+    /// the channel the DAC is playing while multiplexed
+    playing: usize,
+}
+
+impl N163{
+    pub fn new(mixing: N163Mixing) -> Self{
+        Self {
+            ram: [0; 128],
+            address: 0,
+            auto_increment: false,
+            mixing,
+            divider: CHANNEL_CYCLES,
+            current: 0x78,
+            outputs: [0; 8],
+            playing: 7,
+        }
+    }
+
+    pub fn set_mixing(&mut self, mixing: N163Mixing){
+        self.mixing = mixing;
+    }
+
+    /// 1-8, from the high bits of $7F
+    fn channel_count(&self) -> u8{
+        ((self.ram[0x7F] >> 4) & 0x07) + 1
+    }
+
+    /// a 4 bit sample, RAM holds two per byte with the low nibble first
+    fn sample(&self, index: u8) -> u8{
+        let byte = self.ram[(index >> 1) as usize];
+        if index & 1 == 0 { byte & 0x0F } else { byte >> 4 }
+    }
+
+    /// runs the chip for a single CPU cycle
+    pub fn clock(&mut self){
+        self.divider -= 1;
+        if self.divider > 0{
+            return;
+        }
+        self.divider = CHANNEL_CYCLES;
+
+        let base = self.current as usize;
+        let regs = &self.ram[base..base + 8];
+        let frequency = regs[0] as u32 | (regs[2] as u32) << 8 | (regs[4] as u32 & 0x03) << 16;
+        let length = 256 - (regs[4] as u32 & 0xFC);
+        let mut phase = regs[1] as u32 | (regs[3] as u32) << 8 | (regs[5] as u32) << 16;
+        phase = (phase + frequency) % (length << 16);
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+
+        let sample = self.sample(((phase >> 16) as u8).wrapping_add(self.ram[base + 6]));
+        let volume = self.ram[base + 7] & 0x0F;
+        let channel = (base - 0x40) / 8;
+        self.outputs[channel] = (sample as i16 - 8) * volume as i16;
+        self.playing = channel;
+
+        // channels count down from $78 and wrap after the last enabled one
+        let lowest = 0x80 - 8 * self.channel_count();
+        self.current = if self.current <= lowest { 0x78 } else { self.current - 8 };
+    }
+
+    /// -120 to 105 for a single channel at full volume
+    pub fn output(&self) -> f32{
+        match self.mixing{
+            N163Mixing::Multiplexed => self.outputs[self.playing] as f32,
+            N163Mixing::HissFree => {
+                let count = self.channel_count() as usize;
+                let sum: i16 = self.outputs[8 - count..].iter().sum();
+                sum as f32 / count as f32
+            }
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8){
+        match address{
+            0xF800 => {
+                let reg = AddressPort(value);
+                self.address = reg.address();
+                self.auto_increment = reg.auto_increment();
+            }
+            0x4800 => {
+                self.ram[self.address as usize] = value;
+                if self.auto_increment{
+                    self.address = (self.address + 1) & 0x7F;
+                }
+            }
+            // addresses the chip doesn't decode are ignored
+            _ => {}
+        }
+    }
+}
+
+impl Default for N163{
+    fn default() -> Self {
+        Self::new(N163Mixing::default())
+    }
+}
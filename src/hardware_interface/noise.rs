@@ -0,0 +1,141 @@
+use super::{envelope::Envelope, length_counter::LengthCounter, Region};
+
+/// timer periods in CPU cycles
+const NOISE_PERIODS_NTSC: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const NOISE_PERIODS_PAL: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+bitfield!{
+    struct Control(u8);
+    u8;
+    halt, _: 5;
+    constant, _: 4;
+    volume, _: 3, 0;
+}
+
+bitfield!{
+    struct Period(u8);
+    u8;
+    mode, _: 7;
+    index, _: 3, 0;
+}
+
+#[derive(Debug)]
+pub struct Noise{
+    periods: &'static [u16; 16],
+    mode: bool,
+    shift: u16,
+    timer_period: u16,
+    timer: u16,
+    envelope: Envelope,
+    length_counter: LengthCounter,
+}
+
+impl Noise{
+    pub fn new(region: Region) -> Self{
+        let periods = match region{
+            Region::Ntsc | Region::Dendy => &NOISE_PERIODS_NTSC,
+            Region::Pal => &NOISE_PERIODS_PAL,
+        };
+        Self {
+            periods,
+            mode: false,
+            shift: 1,
+            timer_period: periods[0],
+            timer: 0,
+            envelope: Default::default(),
+            length_counter: Default::default(),
+        }
+    }
+
+    /// `register` is the offset from $400C (0-3)
+    pub fn write_register(&mut self, register: u16, value: u8){
+        match register{
+            0 => {
+                let reg = Control(value);
+                self.length_counter.set_halt(reg.halt());
+                self.envelope.write_control(reg.halt(), reg.constant(), reg.volume());
+            }
+            1 => {}
+            2 => {
+                let reg = Period(value);
+                self.mode = reg.mode();
+                self.timer_period = self.periods[reg.index() as usize];
+            }
+            3 => {
+                self.length_counter.load(value >> 3);
+                self.envelope.restart();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool){
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn active(&self) -> bool{
+        self.length_counter.active()
+    }
+
+    /// clocked once every CPU cycle, the period table is in CPU cycles
+    pub fn clock_timer(&mut self){
+        if self.timer == 0{
+            self.timer = self.timer_period - 1;
+            // short mode taps bit 6 giving a 93 step metallic loop
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        }else{
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self){
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self){
+        self.length_counter.clock();
+    }
+
+    pub fn output(&self) -> u8{
+        if !self.length_counter.active() || self.shift & 1 == 1{
+            0
+        }else{
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// shift register steps until it's back where it started
+    fn loop_length(mode: u8) -> usize{
+        let mut noise = Noise::new(Region::Ntsc);
+        noise.write_register(2, mode);
+        let start = noise.shift;
+        let mut steps = 0;
+        loop{
+            let shift = noise.shift;
+            noise.clock_timer();
+            if noise.shift != shift{
+                steps += 1;
+                if noise.shift == start{
+                    return steps;
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn short_mode_loops_in_93_steps(){
+        assert_eq!(loop_length(0x80), 93);
+        assert_eq!(loop_length(0x00), 32767);
+    }
+}
@@ -0,0 +1,238 @@
+use super::{envelope::Envelope, length_counter::LengthCounter};
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+bitfield!{
+    struct Control(u8);
+    u8;
+    duty, _: 7, 6;
+    halt, _: 5;
+    constant, _: 4;
+    volume, _: 3, 0;
+}
+
+bitfield!{
+    struct SweepControl(u8);
+    u8;
+    enabled, _: 7;
+    period, _: 6, 4;
+    negate, _: 3;
+    shift, _: 2, 0;
+}
+
+/// Which pulse a channel is, the 2A03's two differ in how their sweep
+/// units negate and the MMC5's have none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PulseChannel{
+    /// $4000-$4003, negates with one's complement
+    One,
+    /// $4004-$4007, negates with two's complement
+    Two,
+    /// the MMC5's copies have no sweep unit, so nothing ever mutes them
+    Mmc5,
+}
+
+#[derive(Debug)]
+struct Sweep{
+    ones_complement: bool,
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    divider: u8,
+    reload: bool,
+}
+
+impl Sweep{
+    fn new(channel: PulseChannel) -> Self{
+        Self {
+            ones_complement: channel == PulseChannel::One,
+            enabled: false,
+            period: 0,
+            negate: false,
+            shift: 0,
+            divider: 0,
+            reload: false,
+        }
+    }
+
+    fn write_register(&mut self, value: u8){
+        let reg = SweepControl(value);
+        self.enabled = reg.enabled();
+        self.period = reg.period();
+        self.negate = reg.negate();
+        self.shift = reg.shift();
+        self.reload = true;
+    }
+
+    fn target_period(&self, current: u16) -> i32{
+        let change = (current >> self.shift) as i32;
+        if self.negate{
+            if self.ones_complement{
+                current as i32 - change - 1
+            }else{
+                current as i32 - change
+            }
+        }else{
+            current as i32 + change
+        }
+    }
+
+    /// the sweep mutes the channel even while disabled
+    fn muting(&self, current: u16) -> bool{
+        current < 8 || self.target_period(current) > 0x7FF
+    }
+
+    fn clock(&mut self, current: &mut u16){
+        if self.divider == 0 && self.enabled && self.shift > 0 && !self.muting(*current){
+            *current = self.target_period(*current).max(0) as u16;
+        }
+        if self.divider == 0 || self.reload{
+            self.divider = self.period;
+            self.reload = false;
+        }else{
+            self.divider -= 1;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Pulse{
+    duty: u8,
+    step: u8,
+    timer_period: u16,
+    timer: u16,
+    envelope: Envelope,
+    length_counter: LengthCounter,
+    sweep: Option<Sweep>,
+}
+
+impl Pulse{
+    pub fn new(channel: PulseChannel) -> Self{
+        Self {
+            duty: 0,
+            step: 0,
+            timer_period: 0,
+            timer: 0,
+            envelope: Default::default(),
+            length_counter: Default::default(),
+            sweep: match channel{
+                PulseChannel::Mmc5 => None,
+                _ => Some(Sweep::new(channel)),
+            },
+        }
+    }
+
+    /// `register` is the offset from the channel's first register (0-3)
+    pub fn write_register(&mut self, register: u16, value: u8){
+        match register{
+            0 => {
+                let reg = Control(value);
+                self.duty = reg.duty();
+                self.length_counter.set_halt(reg.halt());
+                self.envelope.write_control(reg.halt(), reg.constant(), reg.volume());
+            }
+            1 => {
+                if let Some(sweep) = self.sweep.as_mut(){
+                    sweep.write_register(value);
+                }
+            }
+            2 => {
+                self.timer_period = (self.timer_period & 0x700) | value as u16;
+            }
+            3 => {
+                self.timer_period = (self.timer_period & 0xFF) | ((value as u16 & 0x07) << 8);
+                self.length_counter.load(value >> 3);
+                self.envelope.restart();
+                self.step = 0;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool){
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn active(&self) -> bool{
+        self.length_counter.active()
+    }
+
+    /// clocked once every APU cycle (every other CPU cycle)
+    pub fn clock_timer(&mut self){
+        if self.timer == 0{
+            self.timer = self.timer_period;
+            self.step = (self.step + 1) & 0x07;
+        }else{
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self){
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self){
+        self.length_counter.clock();
+        if let Some(sweep) = self.sweep.as_mut(){
+            sweep.clock(&mut self.timer_period);
+        }
+    }
+
+    pub fn output(&self) -> u8{
+        if !self.length_counter.active()
+            || self.sweep.as_ref().is_some_and(|sweep| sweep.muting(self.timer_period))
+            || DUTY_TABLE[self.duty as usize][self.step as usize] == 0{
+            0
+        }else{
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// a loud pulse on its first duty step, `sweep` goes in the second register
+    fn pulse(channel: PulseChannel, sweep: u8, period: u16) -> Pulse{
+        let mut pulse = Pulse::new(channel);
+        pulse.set_enabled(true);
+        pulse.write_register(0, 0xFF);
+        pulse.write_register(1, sweep);
+        pulse.write_register(2, period as u8);
+        pulse.write_register(3, 0x08 | (period >> 8) as u8);
+        pulse
+    }
+
+    #[test]
+    pub fn sweep_negates_by_channel(){
+        // $100 less $80, the first pulse's one's complement takes one more
+        let mut one = pulse(PulseChannel::One, 0x89, 0x100);
+        let mut two = pulse(PulseChannel::Two, 0x89, 0x100);
+        one.clock_half_frame();
+        two.clock_half_frame();
+        assert_eq!(one.timer_period, 0x7F);
+        assert_eq!(two.timer_period, 0x80);
+    }
+
+    #[test]
+    pub fn sweep_mutes(){
+        // periods under 8 and targets past $7FF mute, even with the sweep off
+        assert_eq!(pulse(PulseChannel::One, 0x08, 7).output(), 0);
+        assert_eq!(pulse(PulseChannel::One, 0x08, 8).output(), 15);
+        assert_eq!(pulse(PulseChannel::Two, 0x00, 0x400).output(), 0);
+        assert_eq!(pulse(PulseChannel::Two, 0x00, 0x3FF).output(), 15);
+        assert_eq!(pulse(PulseChannel::Two, 0x01, 0x555).output(), 15);
+        assert_eq!(pulse(PulseChannel::Two, 0x01, 0x556).output(), 0);
+        // and a muting target is never written back
+        let mut high = pulse(PulseChannel::Two, 0x81, 0x556);
+        high.clock_half_frame();
+        assert_eq!(high.timer_period, 0x556);
+    }
+}
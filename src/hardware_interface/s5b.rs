@@ -0,0 +1,228 @@
+/// CPU cycles per tick of the tone and noise counters
+const PRESCALER: u8 = 16;
+/// the 5 bit DAC level falls 1.5 dB a step
+const STEP_DB: f32 = 1.5;
+
+bitfield!{
+    struct EnvelopeShape(u8);
+    u8;
+    continues, _: 3;
+    attack, _: 2;
+    alternate, _: 1;
+    hold, _: 0;
+}
+
+#[derive(Debug, Default)]
+struct Tone{
+    period: u16,
+    counter: u16,
+    output: bool,
+}
+
+impl Tone{
+    /// the output flips every `period` ticks, so a square lasts 32 * period
+    /// CPU cycles
+    fn clock(&mut self){
+        self.counter += 1;
+        if self.counter >= self.period.max(1){
+            self.counter = 0;
+            self.output = !self.output;
+        }
+    }
+}
+
+/// 17 bit LFSR stepped every other `period` ticks
+#[derive(Debug)]
+struct Noise{
+    period: u8,
+    counter: u8,
+    half: bool,
+    lfsr: u32,
+}
+
+impl Noise{
+    fn clock(&mut self){
+        self.half = !self.half;
+        if self.half{
+            return;
+        }
+        self.counter += 1;
+        if self.counter >= self.period.max(1){
+            self.counter = 0;
+            let bit = (self.lfsr ^ (self.lfsr >> 3)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (bit << 16);
+        }
+    }
+
+    fn output(&self) -> bool{
+        self.lfsr & 1 != 0
+    }
+}
+
+/// 32 step ramps, repeated, alternated or held as $0D picks
+#[derive(Debug, Default)]
+struct Envelope{
+    period: u16,
+    counter: u16,
+    shape: u8,
+    step: u8,
+    rising: bool,
+    holding: bool,
+    level: u8,
+}
+
+impl Envelope{
+    fn restart(&mut self, shape: u8){
+        self.shape = shape;
+        self.counter = 0;
+        self.step = 0;
+        self.rising = EnvelopeShape(shape).attack();
+        self.holding = false;
+        self.level = if self.rising { 0 } else { 31 };
+    }
+
+    fn clock(&mut self){
+        if self.holding{
+            return;
+        }
+        self.counter += 1;
+        if self.counter < self.period.max(1){
+            return;
+        }
+        self.counter = 0;
+        self.step += 1;
+        if self.step < 32{
+            self.level = if self.rising { self.step } else { 31 - self.step };
+            return;
+        }
+
+        let shape = EnvelopeShape(self.shape);
+        self.step = 0;
+        if !shape.continues(){
+            self.holding = true;
+            self.level = 0;
+        }else if shape.hold(){
+            self.holding = true;
+            let end = self.rising != shape.alternate();
+            self.level = if end { 31 } else { 0 };
+        }else{
+            if shape.alternate(){
+                self.rising = !self.rising;
+            }
+            self.level = if self.rising { 0 } else { 31 };
+        }
+    }
+}
+
+/// Sunsoft's 5B, the FME-7 mapper with a YM2149 inside: three squares,
+/// one noise generator and one envelope generator shared between them.
+#[derive(Debug)]
+pub struct S5B{
+    address: u8,
+    registers: [u8; 16],
+    tones: [Tone; 3],
+    noise: Noise,
+    envelope: Envelope,
+    divider: u8,
+    /// amplitude of each DAC level, 0 is silent
+    levels: [f32; 32],
+}
+
+impl S5B{
+    pub fn new() -> Self{
+        let mut levels = [0.0; 32];
+        for (level, out) in levels.iter_mut().enumerate().skip(1){
+            *out = 10f32.powf((level as f32 - 31.0) * STEP_DB / 20.0);
+        }
+        Self {
+            address: 0,
+            registers: [0; 16],
+            tones: Default::default(),
+            noise: Noise { period: 0, counter: 0, half: false, lfsr: 1 },
+            envelope: Default::default(),
+            divider: PRESCALER,
+            levels,
+        }
+    }
+
+    /// the value last written to one of the 16 registers
+    pub fn register(&self, register: u8) -> u8{
+        self.registers[register as usize & 0x0F]
+    }
+
+    /// runs the chip for a single CPU cycle, the envelope's 32 steps go
+    /// twice as fast as the other counters so a ramp lasts 256 * period
+    /// cycles
+    pub fn clock(&mut self){
+        self.divider -= 1;
+        if self.divider == PRESCALER / 2{
+            self.envelope.clock();
+        }
+        if self.divider > 0{
+            return;
+        }
+        self.divider = PRESCALER;
+        for tone in self.tones.iter_mut(){
+            tone.clock();
+        }
+        self.noise.clock();
+        self.envelope.clock();
+    }
+
+    /// each channel is 0.0 to 1.0
+    pub fn output(&self) -> f32{
+        let mixer = self.registers[7];
+        let mut out = 0.0;
+        for (channel, tone) in self.tones.iter().enumerate(){
+            let tone_on = tone.output || mixer & (1 << channel) != 0;
+            let noise_on = self.noise.output() || mixer & (8 << channel) != 0;
+            if !(tone_on && noise_on){
+                continue;
+            }
+            let volume = self.registers[8 + channel];
+            let level = if volume & 0x10 != 0{
+                self.envelope.level
+            }else if volume & 0x0F == 0{
+                0
+            }else{
+                (volume & 0x0F) * 2 + 1
+            };
+            out += self.levels[level as usize];
+        }
+        out
+    }
+
+    fn write_data(&mut self, value: u8){
+        let register = self.address as usize;
+        if register > 0x0F{
+            return;
+        }
+        self.registers[register] = value;
+        match register{
+            0..=5 => {
+                let channel = register / 2;
+                self.tones[channel].period = self.registers[channel * 2] as u16
+                    | ((self.registers[channel * 2 + 1] as u16 & 0x0F) << 8);
+            }
+            6 => self.noise.period = value & 0x1F,
+            11 | 12 => self.envelope.period = self.registers[11] as u16 | (self.registers[12] as u16) << 8,
+            13 => self.envelope.restart(value & 0x0F),
+            _ => {}
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8){
+        match address{
+            0xC000 => self.address = value,
+            0xE000 => self.write_data(value),
+            // addresses the chip doesn't decode are ignored
+            _ => {}
+        }
+    }
+}
+
+impl Default for S5B{
+    fn default() -> Self {
+        Self::new()
+    }
+}
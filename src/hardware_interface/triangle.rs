@@ -0,0 +1,166 @@
+use super::length_counter::LengthCounter;
+
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+bitfield!{
+    struct LinearControl(u8);
+    u8;
+    control, _: 7;
+    reload, _: 6, 0;
+}
+
+#[derive(Default, Debug)]
+struct LinearCounter{
+    control: bool,
+    reload_value: u8,
+    reload: bool,
+    counter: u8,
+}
+
+impl LinearCounter{
+    fn clock(&mut self){
+        if self.reload{
+            self.counter = self.reload_value;
+        }else if self.counter > 0{
+            self.counter -= 1;
+        }
+        if !self.control{
+            self.reload = false;
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Triangle{
+    step: u8,
+    timer_period: u16,
+    timer: u16,
+    linear_counter: LinearCounter,
+    length_counter: LengthCounter,
+}
+
+impl Triangle{
+    pub fn new() -> Self{
+        Default::default()
+    }
+
+    /// `register` is the offset from $4008 (0-3)
+    pub fn write_register(&mut self, register: u16, value: u8){
+        match register{
+            0 => {
+                let reg = LinearControl(value);
+                self.linear_counter.control = reg.control();
+                self.linear_counter.reload_value = reg.reload();
+                self.length_counter.set_halt(reg.control());
+            }
+            1 => {}
+            2 => {
+                self.timer_period = (self.timer_period & 0x700) | value as u16;
+            }
+            3 => {
+                self.timer_period = (self.timer_period & 0xFF) | ((value as u16 & 0x07) << 8);
+                self.length_counter.load(value >> 3);
+                self.linear_counter.reload = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool){
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn active(&self) -> bool{
+        self.length_counter.active()
+    }
+
+    /// clocked once every CPU cycle
+    ///
+    /// Like hardware, periods below 2 keep the sequencer stepping at an
+    /// ultrasonic rate that averages out to about 7.5, so those notes pop
+    /// where they start and stop. FamiTracker freezes the sequencer instead.
+    pub fn clock_timer(&mut self){
+        if self.timer == 0{
+            self.timer = self.timer_period;
+            if self.linear_counter.counter > 0 && self.length_counter.active(){
+                self.step = (self.step + 1) & 0x1F;
+            }
+        }else{
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self){
+        self.linear_counter.clock();
+    }
+
+    pub fn clock_half_frame(&mut self){
+        self.length_counter.clock();
+    }
+
+    /// the triangle is never silenced, a halted sequencer keeps
+    /// outputting its current step
+    pub fn output(&self) -> u8{
+        TRIANGLE_SEQUENCE[self.step as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn playing(control: u8, period: u16) -> Triangle{
+        let mut triangle = Triangle::new();
+        triangle.set_enabled(true);
+        triangle.write_register(0, control);
+        triangle.write_register(2, period as u8);
+        triangle.write_register(3, 0x08 | (period >> 8) as u8);
+        triangle
+    }
+
+    /// whether the sequencer moves over a whole period
+    fn steps(triangle: &mut Triangle) -> bool{
+        let step = triangle.step;
+        for _ in 0..=triangle.timer_period{
+            triangle.clock_timer();
+        }
+        triangle.step != step
+    }
+
+    #[test]
+    pub fn linear_counter_gates_the_sequencer(){
+        // a reload of 2 is loaded on the first quarter frame and counts down
+        // on the next two
+        let mut triangle = playing(0x02, 0x40);
+        assert!(!steps(&mut triangle));
+        triangle.clock_quarter_frame();
+        assert!(steps(&mut triangle));
+        triangle.clock_quarter_frame();
+        assert!(steps(&mut triangle));
+        triangle.clock_quarter_frame();
+        assert!(!steps(&mut triangle));
+        // writing the last register reloads it on the next quarter frame
+        triangle.write_register(3, 0x08);
+        triangle.clock_quarter_frame();
+        assert!(steps(&mut triangle));
+
+        // with the control bit set it reloads on every quarter frame
+        let mut triangle = playing(0x82, 0x40);
+        for _ in 0..8{
+            triangle.clock_quarter_frame();
+        }
+        assert!(steps(&mut triangle));
+    }
+
+    #[test]
+    pub fn ultrasonic_periods_keep_stepping(){
+        for period in [0, 1]{
+            let mut triangle = playing(0x82, period);
+            triangle.clock_quarter_frame();
+            assert!(steps(&mut triangle), "{}", period);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+bitfield!{
+    struct PulseControl(u8);
+    u8;
+    mode, _: 7;
+    duty, _: 6, 4;
+    volume, _: 3, 0;
+}
+
+bitfield!{
+    struct FrequencyControl(u8);
+    u8;
+    shift_8, _: 2;
+    shift_4, _: 1;
+    halt, _: 0;
+}
+
+/// 12 bit divider shared by all three channels, $x001/$x002
+#[derive(Debug, Default)]
+struct Timer{
+    period: u16,
+    divider: u16,
+    enabled: bool,
+}
+
+impl Timer{
+    fn write_low(&mut self, value: u8){
+        self.period = (self.period & 0x0F00) | value as u16;
+    }
+
+    fn write_high(&mut self, value: u8){
+        self.period = (self.period & 0x00FF) | ((value as u16 & 0x0F) << 8);
+        self.enabled = value & 0x80 != 0;
+    }
+
+    /// true when the divider reloads
+    fn clock(&mut self, shift: u8) -> bool{
+        if self.divider == 0{
+            self.divider = self.period >> shift;
+            true
+        }else{
+            self.divider -= 1;
+            false
+        }
+    }
+}
+
+/// $9000-$9002 and $A000-$A002
+#[derive(Debug)]
+struct Pulse{
+    timer: Timer,
+    mode: bool,
+    duty: u8,
+    volume: u8,
+    step: u8,
+}
+
+impl Pulse{
+    fn new() -> Self{
+        Self { timer: Timer::default(), mode: false, duty: 0, volume: 0, step: 15 }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8){
+        match address{
+            0 => {
+                let reg = PulseControl(value);
+                self.mode = reg.mode();
+                self.duty = reg.duty();
+                self.volume = reg.volume();
+            }
+            1 => self.timer.write_low(value),
+            2 => {
+                self.timer.write_high(value);
+                if !self.timer.enabled{
+                    self.step = 15;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn clock(&mut self, shift: u8){
+        if self.timer.enabled && self.timer.clock(shift){
+            self.step = self.step.wrapping_sub(1) & 0x0F;
+        }
+    }
+
+    fn output(&self) -> u8{
+        if self.timer.enabled && (self.mode || self.step <= self.duty){
+            self.volume
+        }else{
+            0
+        }
+    }
+}
+
+/// $B000-$B002, adds the rate to an accumulator every other step and
+/// resets it on the seventh
+#[derive(Debug)]
+struct Sawtooth{
+    timer: Timer,
+    rate: u8,
+    accumulator: u8,
+    step: u8,
+}
+
+impl Sawtooth{
+    fn new() -> Self{
+        Self { timer: Timer::default(), rate: 0, accumulator: 0, step: 0 }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8){
+        match address{
+            0 => self.rate = value & 0x3F,
+            1 => self.timer.write_low(value),
+            2 => {
+                self.timer.write_high(value);
+                if !self.timer.enabled{
+                    self.accumulator = 0;
+                    self.step = 0;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn clock(&mut self, shift: u8){
+        if !(self.timer.enabled && self.timer.clock(shift)){
+            return;
+        }
+        self.step += 1;
+        if self.step == 14{
+            self.step = 0;
+            self.accumulator = 0;
+        }else if self.step & 1 == 0{
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8{
+        self.accumulator >> 3
+    }
+}
+
+/// Konami's VRC6 expansion audio: two pulses with eight duty settings and
+/// a sawtooth.
+#[derive(Debug)]
+pub struct Vrc6{
+    pulse_0: Pulse,
+    pulse_1: Pulse,
+    sawtooth: Sawtooth,
+    halt: bool,
+    shift: u8,
+}
+
+impl Vrc6{
+    pub fn new() -> Self{
+        Self {
+            pulse_0: Pulse::new(),
+            pulse_1: Pulse::new(),
+            sawtooth: Sawtooth::new(),
+            halt: false,
+            shift: 0,
+        }
+    }
+
+    /// runs the chip for a single CPU cycle
+    pub fn clock(&mut self){
+        if self.halt{
+            return;
+        }
+        self.pulse_0.clock(self.shift);
+        self.pulse_1.clock(self.shift);
+        self.sawtooth.clock(self.shift);
+    }
+
+    /// the summed 6 bit DAC input, 0..=61
+    pub fn output(&self) -> u8{
+        self.pulse_0.output() + self.pulse_1.output() + self.sawtooth.output()
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8){
+        match address{
+            0x9000..=0x9002 => self.pulse_0.write_register(address - 0x9000, value),
+            0x9003 => {
+                let reg = FrequencyControl(value);
+                self.halt = reg.halt();
+                self.shift = if reg.shift_8(){ 8 }else if reg.shift_4(){ 4 }else{ 0 };
+            }
+            0xA000..=0xA002 => self.pulse_1.write_register(address - 0xA000, value),
+            0xB000..=0xB002 => self.sawtooth.write_register(address - 0xB000, value),
+            // addresses the chip doesn't decode are ignored
+            _ => {}
+        }
+    }
+}
+
+impl Default for Vrc6{
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,400 @@
+use std::f32::consts::PI;
+
+use super::CPU_CLOCK_NTSC;
+
+/// the chip makes one sample every 36 CPU cycles, about 49.7 kHz
+const CYCLES_PER_SAMPLE: u8 = 36;
+const SAMPLE_RATE: f32 = CPU_CLOCK_NTSC as f32 / CYCLES_PER_SAMPLE as f32;
+
+/// patches 1-15 as eight register bytes each, patch 0 is the custom one
+/// in $00-$07, from the nesdev wiki's dump of the VRC7 ROM
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+/// frequency multiple of each MULT setting
+const MULTIPLIERS: [f32; 16] = [0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0];
+/// key scale attenuation in dB at block 7 for the top four bits of F-Num,
+/// at 6 dB per octave
+const KSL_TABLE: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0,
+];
+/// divides the key scale attenuation for KSL 0-3, 0 turns it off
+const KSL_SHIFT: [f32; 4] = [f32::INFINITY, 4.0, 2.0, 1.0];
+/// milliseconds from silence to full level at attack rate 1
+const ATTACK_TIME: f32 = 2826.24;
+/// milliseconds to fall through `SILENCE` at decay rate 1
+const DECAY_TIME: f32 = 39280.64;
+/// attenuation the envelope treats as silent
+const SILENCE: f32 = 96.0;
+/// release rate while the channel's sustain bit is set
+const SUSTAIN_RELEASE: u8 = 5;
+/// release rate of a percussive patch once the key is off
+const PERCUSSIVE_RELEASE: u8 = 7;
+const TREMOLO_RATE: f32 = 3.7;
+const TREMOLO_DEPTH: f32 = 4.8;
+const VIBRATO_RATE: f32 = 6.4;
+/// in cents either side
+const VIBRATO_DEPTH: f32 = 14.0;
+/// a full scale modulator moves the carrier this many cycles
+const MODULATION_INDEX: f32 = 4.0;
+
+bitfield!{
+    struct OperatorControl(u8);
+    u8;
+    tremolo, _: 7;
+    vibrato, _: 6;
+    sustained, _: 5;
+    key_scale_rate, _: 4;
+    multiplier, _: 3, 0;
+}
+
+/// One operator's half of a patch.
+#[derive(Debug, Clone, Copy, Default)]
+struct OperatorPatch{
+    tremolo: bool,
+    vibrato: bool,
+    /// the envelope holds at the sustain level until the key is released,
+    /// otherwise it keeps falling like a struck note
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: u8,
+    key_scale_level: u8,
+    rectify: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+/// Registers $00-$07 unpacked, the modulator's total level and feedback
+/// only exist on the modulator.
+#[derive(Debug, Clone, Copy, Default)]
+struct Patch{
+    modulator: OperatorPatch,
+    carrier: OperatorPatch,
+    total_level: u8,
+    feedback: u8,
+}
+
+impl Patch{
+    fn new(regs: &[u8; 8]) -> Self{
+        let operator = |control: u8, ksl: u8, rectify: bool, rates: u8, levels: u8| {
+            let control = OperatorControl(control);
+            OperatorPatch{
+                tremolo: control.tremolo(),
+                vibrato: control.vibrato(),
+                sustained: control.sustained(),
+                key_scale_rate: control.key_scale_rate(),
+                multiplier: control.multiplier(),
+                key_scale_level: ksl >> 6,
+                rectify,
+                attack: rates >> 4,
+                decay: rates & 0x0F,
+                sustain_level: levels >> 4,
+                release: levels & 0x0F,
+            }
+        };
+        Self {
+            modulator: operator(regs[0], regs[2], regs[3] & 0x08 != 0, regs[4], regs[6]),
+            carrier: operator(regs[1], regs[3], regs[3] & 0x10 != 0, regs[5], regs[7]),
+            total_level: regs[2] & 0x3F,
+            feedback: regs[3] & 0x07,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EnvelopeState{
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Operator{
+    /// in cycles of the sine
+    phase: f32,
+    state: EnvelopeState,
+    /// in dB
+    attenuation: f32,
+    /// the last two outputs, for the modulator's feedback
+    history: [f32; 2],
+}
+
+impl Operator{
+    fn new() -> Self{
+        Self { phase: 0.0, state: EnvelopeState::Release, attenuation: SILENCE, history: [0.0; 2] }
+    }
+
+    fn key_on(&mut self){
+        self.phase = 0.0;
+        self.state = EnvelopeState::Attack;
+    }
+
+    /// dB per sample to fall through `SILENCE` at `rate`, scaled by key
+    /// scaling, 0 holds
+    fn decay_step(rate: u8, key_scale: u8) -> f32{
+        if rate == 0{
+            return 0.0;
+        }
+        let rate = (rate * 4 + key_scale).min(63);
+        let time = DECAY_TIME * 4.0 / (4 + (rate & 3)) as f32 / 2f32.powi((rate >> 2) as i32 - 1);
+        SILENCE / (time / 1000.0 * SAMPLE_RATE)
+    }
+
+    /// the attack curves towards full level, this is the fraction of the
+    /// remaining attenuation removed per sample
+    fn attack_step(rate: u8, key_scale: u8) -> f32{
+        if rate == 0{
+            return 0.0;
+        }
+        let rate = (rate * 4 + key_scale).min(63);
+        if rate >= 60{
+            return 1.0;
+        }
+        let time = ATTACK_TIME * 4.0 / (4 + (rate & 3)) as f32 / 2f32.powi((rate >> 2) as i32 - 1);
+        let samples = time / 1000.0 * SAMPLE_RATE;
+        1.0 - (0.1 / SILENCE).powf(1.0 / samples)
+    }
+
+    fn clock_envelope(&mut self, patch: &OperatorPatch, key_scale: u8, key_on: bool, sustain: bool){
+        let key_scale = if patch.key_scale_rate { key_scale } else { key_scale >> 2 };
+        match self.state{
+            EnvelopeState::Attack => {
+                self.attenuation -= self.attenuation * Self::attack_step(patch.attack, key_scale);
+                if self.attenuation < 0.1{
+                    self.attenuation = 0.0;
+                    self.state = EnvelopeState::Decay;
+                }
+            }
+            EnvelopeState::Decay => {
+                let level = patch.sustain_level as f32 * 3.0;
+                self.attenuation += Self::decay_step(patch.decay, key_scale);
+                if self.attenuation >= level{
+                    self.attenuation = level;
+                    self.state = EnvelopeState::Sustain;
+                }
+            }
+            EnvelopeState::Sustain => {
+                if !patch.sustained{
+                    self.attenuation += Self::decay_step(patch.release, key_scale);
+                }
+            }
+            EnvelopeState::Release => {
+                let rate = if sustain{
+                    SUSTAIN_RELEASE
+                }else if patch.sustained{
+                    patch.release
+                }else{
+                    PERCUSSIVE_RELEASE
+                };
+                self.attenuation += Self::decay_step(rate, key_scale);
+            }
+        }
+        if !key_on && self.state != EnvelopeState::Release{
+            self.state = EnvelopeState::Release;
+        }
+        self.attenuation = self.attenuation.min(SILENCE);
+    }
+
+    /// one sample, `modulation` in cycles
+    fn output(&mut self, patch: &OperatorPatch, attenuation: f32, modulation: f32) -> f32{
+        let attenuation = self.attenuation + attenuation;
+        if attenuation >= SILENCE{
+            return 0.0;
+        }
+        let mut out = (2.0 * PI * (self.phase + modulation)).sin();
+        if patch.rectify && out < 0.0{
+            out = 0.0;
+        }
+        out * 10f32.powf(-attenuation / 20.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Channel{
+    fnum: u16,
+    block: u8,
+    key_on: bool,
+    sustain: bool,
+    instrument: u8,
+    /// 3 dB steps of attenuation
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+}
+
+impl Channel{
+    fn new() -> Self{
+        Self {
+            fnum: 0,
+            block: 0,
+            key_on: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+            modulator: Operator::new(),
+            carrier: Operator::new(),
+        }
+    }
+
+    /// the key scale rate before KSR picks between its two ranges
+    fn key_scale(&self) -> u8{
+        (self.block << 1) | (self.fnum >> 8) as u8
+    }
+
+    fn key_scale_level(&self, ksl: u8) -> f32{
+        let level = KSL_TABLE[(self.fnum >> 5) as usize] - 6.0 * (7 - self.block) as f32;
+        level.max(0.0) / KSL_SHIFT[ksl as usize]
+    }
+
+    fn sample(&mut self, patch: &Patch, tremolo: f32, vibrato: f32) -> f32{
+        let key_scale = self.key_scale();
+        self.modulator.clock_envelope(&patch.modulator, key_scale, self.key_on, self.sustain);
+        self.carrier.clock_envelope(&patch.carrier, key_scale, self.key_on, self.sustain);
+
+        let base = self.fnum as f32 * 2f32.powi(self.block as i32 - 1) / (1 << 18) as f32;
+        for (operator, op_patch) in [(&mut self.modulator, &patch.modulator), (&mut self.carrier, &patch.carrier)]{
+            let mut step = base * MULTIPLIERS[op_patch.multiplier as usize];
+            if op_patch.vibrato{
+                step *= vibrato;
+            }
+            operator.phase = (operator.phase + step).fract();
+        }
+
+        let am = |op: &OperatorPatch| if op.tremolo { tremolo } else { 0.0 };
+        let feedback = if patch.feedback > 0{
+            (self.modulator.history[0] + self.modulator.history[1]) / 2.0 * 2f32.powi(patch.feedback as i32 - 5) / 2.0
+        }else{
+            0.0
+        };
+        let mod_attenuation = patch.total_level as f32 * 0.75
+            + self.key_scale_level(patch.modulator.key_scale_level)
+            + am(&patch.modulator);
+        let modulator = self.modulator.output(&patch.modulator, mod_attenuation, feedback);
+        self.modulator.history = [self.modulator.history[1], modulator];
+
+        let car_attenuation = self.volume as f32 * 3.0
+            + self.key_scale_level(patch.carrier.key_scale_level)
+            + am(&patch.carrier);
+        self.carrier.output(&patch.carrier, car_attenuation, modulator * MODULATION_INDEX)
+    }
+}
+
+/// Konami's VRC7 expansion audio, a six channel cut of Yamaha's YM2413:
+/// two operator FM voices playing one custom patch or 15 built in ones.
+#[derive(Debug)]
+pub struct Vrc7{
+    address: u8,
+    custom: [u8; 8],
+    patches: [Patch; 16],
+    channels: [Channel; 6],
+    divider: u8,
+    lfo_time: f32,
+    output: f32,
+}
+
+impl Vrc7{
+    pub fn new() -> Self{
+        let mut patches = [Patch::default(); 16];
+        for (patch, regs) in patches.iter_mut().skip(1).zip(PATCHES.iter()){
+            *patch = Patch::new(regs);
+        }
+        Self {
+            address: 0,
+            custom: [0; 8],
+            patches,
+            channels: [Channel::new(); 6],
+            divider: CYCLES_PER_SAMPLE,
+            lfo_time: 0.0,
+            output: 0.0,
+        }
+    }
+
+    /// runs the chip for a single CPU cycle
+    pub fn clock(&mut self){
+        self.divider -= 1;
+        if self.divider > 0{
+            return;
+        }
+        self.divider = CYCLES_PER_SAMPLE;
+
+        self.lfo_time += 1.0 / SAMPLE_RATE;
+        let tremolo = TREMOLO_DEPTH * (1.0 - (2.0 * PI * TREMOLO_RATE * self.lfo_time).cos()) / 2.0;
+        let cents = VIBRATO_DEPTH * (2.0 * PI * VIBRATO_RATE * self.lfo_time).sin();
+        let vibrato = 2f32.powf(cents / 1200.0);
+        let patches = &self.patches;
+        self.output = self.channels
+            .iter_mut()
+            .map(|channel| channel.sample(&patches[channel.instrument as usize], tremolo, vibrato))
+            .sum();
+    }
+
+    /// each channel swings -1.0 to 1.0 at full volume
+    pub fn output(&self) -> f32{
+        self.output
+    }
+
+    fn write_data(&mut self, value: u8){
+        let register = self.address;
+        match register{
+            0x00..=0x07 => {
+                self.custom[register as usize] = value;
+                self.patches[0] = Patch::new(&self.custom);
+            }
+            0x10..=0x15 => {
+                let channel = &mut self.channels[(register - 0x10) as usize];
+                channel.fnum = (channel.fnum & 0x100) | value as u16;
+            }
+            0x20..=0x25 => {
+                let channel = &mut self.channels[(register - 0x20) as usize];
+                channel.fnum = (channel.fnum & 0xFF) | ((value as u16 & 1) << 8);
+                channel.block = (value >> 1) & 0x07;
+                channel.sustain = value & 0x20 != 0;
+                let key_on = value & 0x10 != 0;
+                if key_on && !channel.key_on{
+                    channel.modulator.key_on();
+                    channel.carrier.key_on();
+                }
+                channel.key_on = key_on;
+            }
+            0x30..=0x35 => {
+                let channel = &mut self.channels[(register - 0x30) as usize];
+                channel.instrument = value >> 4;
+                channel.volume = value & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8){
+        match address{
+            0x9010 => self.address = value,
+            0x9030 => self.write_data(value),
+            // addresses the chip doesn't decode are ignored
+            _ => {}
+        }
+    }
+}
+
+impl Default for Vrc7{
+    fn default() -> Self {
+        Self::new()
+    }
+}
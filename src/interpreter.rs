@@ -1,97 +1,369 @@
-use crate::{sound_file::*, hardware_interface::HardwareInterface};
+use std::sync::{Arc, Mutex};
 
-pub struct Interpreter<'a>{
-    file: &'a SoundFile,
-    current_track: Option<&'a Track>,
-    row: u8,
-    pattern_order: u8,
+use crate::{sound_file::*, hardware_interface::{HardwareInterface, Board, Region}};
+
+use self::{channel::{Channel, ChannelKind}, period::PeriodTables};
+
+mod channel;
+mod period;
+mod sequence;
+
+/// engine rates used when PLAYBACKRATE asks for the machine default
+const DEFAULT_RATE_NTSC: f64 = 60.0;
+const DEFAULT_RATE_PAL: f64 = 50.0;
+/// engine rates used when PLAYBACKRATE asks to follow the video refresh
+const VIDEO_RATE_NTSC: f64 = 60.0988;
+const VIDEO_RATE_PAL: f64 = 50.0070;
+
+pub struct Interpreter{
+    file: Arc<SoundFile>,
+    player: Arc<Mutex<Player>>,
+    #[allow(unused)]
     audio: HardwareInterface,
 }
 
-impl<'a> Interpreter<'a>{
-    pub fn new(file: &'a SoundFile) -> Self{
+impl Interpreter{
+    /// starts playing the first track of `file`, the audio thread's player
+    /// shares it
+    pub fn new(file: Arc<SoundFile>) -> Self{
+        let player = Player::new(file.clone());
+        let rate = player.engine_rate();
+        let player = Arc::new(Mutex::new(player));
+
+        let mut audio = HardwareInterface::new();
+        let driver = player.clone();
+        audio.set_driver(rate, Box::new(move |board| driver.lock().unwrap().tick(board)));
         Self {
             file,
-            current_track: Default::default(),
-            row: 0,
-            pattern_order: 0, 
-            audio: HardwareInterface::new()
+            player,
+            audio,
         }
     }
 
     pub fn start_track(&mut self, name: &str){
+        let index = self.file.tracks.iter().position(|track| track.name == name);
+        let mut player = self.player.lock().unwrap();
+        match index{
+            Some(index) => player.start(index),
+            None => player.stop(),
+        }
+    }
+
+    pub fn reset(&mut self){
+        self.player.lock().unwrap().reset();
+    }
+
+    /// plays on another console than the module's MACHINE asks for
+    pub fn set_region(&mut self, region: Region){
+        let rate = {
+            let mut player = self.player.lock().unwrap();
+            player.set_region(region);
+            player.engine_rate()
+        };
+        let driver = self.player.clone();
+        self.audio.set_driver(rate, Box::new(move |board| driver.lock().unwrap().tick(board)));
+    }
+}
+
+/// FamiTracker's playback engine, ticked at the engine rate by the audio
+/// thread.
+pub struct Player{
+    file: Arc<SoundFile>,
+    track: Option<usize>,
+    frame: usize,
+    row: usize,
+    speed: u32,
+    tempo: u32,
+    tempo_accum: i32,
+    tempo_decrement: i32,
+    tempo_remainder: i32,
+    tables: PeriodTables,
+    /// Bxx, the frame the next row jumps to
+    jump: Option<usize>,
+    /// Dxx, the row of the next frame the next row skips to
+    skip: Option<usize>,
+    /// Cxx, playback stops after this row
+    halt: bool,
+    /// one per track column, `None` for columns of chips we don't drive
+    channels: Vec<Option<Channel>>,
+    /// the next tick starts by putting the board in a known state
+    init_hardware: bool,
+    region: Region,
+}
+
+impl Player{
+    pub fn new(file: Arc<SoundFile>) -> Self{
+        let region = Region::from_machine(file.machine);
+        let tables = Self::tables(&file, region);
+        let mut player = Self {
+            file,
+            track: None,
+            frame: 0,
+            row: 0,
+            speed: 6,
+            tempo: 150,
+            tempo_accum: 0,
+            tempo_decrement: 0,
+            tempo_remainder: 0,
+            tables,
+            jump: None,
+            skip: None,
+            halt: false,
+            channels: Vec::new(),
+            init_hardware: true,
+            region,
+        };
+        if !player.file.tracks.is_empty(){
+            player.start(0);
+        }
+        player
+    }
+
+    /// ticks per second, from PLAYBACKRATE and the region, a Dendy ticks
+    /// at the PAL rates
+    pub fn engine_rate(&self) -> f64{
+        let pal = self.region != Region::Ntsc;
+        match self.file.playbackrate{
+            (1, period) if period > 0 => 1_000_000.0 / period as f64,
+            (2, _) => if pal { VIDEO_RATE_PAL } else { VIDEO_RATE_NTSC },
+            _ => if pal { DEFAULT_RATE_PAL } else { DEFAULT_RATE_NTSC },
+        }
+    }
+
+    fn tables(file: &SoundFile, region: Region) -> PeriodTables{
+        PeriodTables::new(region, file.tuning, file.vibrato == 1)
+    }
+
+    pub fn region(&self) -> Region{
+        self.region
+    }
+
+    /// retunes the period tables and puts the board in the region on the
+    /// next tick, the engine rate changes with it
+    pub fn set_region(&mut self, region: Region){
+        self.region = region;
+        self.tables = Self::tables(&self.file, region);
+        self.init_hardware = true;
+    }
+
+    pub fn start(&mut self, track: usize){
+        self.track = Some(track);
         self.reset();
-        for track in &self.file.tracks{
-            if track.name == name{
-                self.current_track = Option::Some(track);
-                return;
-            }
+    }
+
+    pub fn stop(&mut self){
+        self.track = None;
+        self.channels.clear();
+        self.init_hardware = true;
+    }
+
+    pub fn playing(&self) -> bool{
+        self.track.is_some()
+    }
+
+    pub fn reset(&mut self){
+        self.frame = 0;
+        self.row = 0;
+        self.tempo_accum = 0;
+        self.jump = None;
+        self.skip = None;
+        self.halt = false;
+        if let Some((speed, tempo)) = self.current_track().map(|track| (track.speed, track.temp)){
+            self.speed = speed;
+            self.tempo = tempo;
         }
-        self.current_track = Option::None;
+        self.setup_speed();
+        let chips = match self.current_track(){
+            Some(_) => self.file.column_chips(),
+            None => Vec::new(),
+        };
+        self.channels = chips
+            .iter()
+            .enumerate()
+            .map(|(column, chip)| {
+                let index = chips[..column].iter().filter(|other| *other == chip).count();
+                ChannelKind::new(*chip, index).map(Channel::new)
+            })
+            .collect();
+        self.init_hardware = true;
     }
 
-    pub fn next_row(&mut self){
-        if let Option::Some(track) = self.current_track{
-            self.play_current_row();
-            self.row += 1;
-            if self.row >= track.pattern_length as u8{
-                self.pattern_order += 1;
-                self.row = 0
-            }
-            if self.pattern_order >= track.pattern_order.len() as u8{
-                //stop the song
-                return;
-            }
+    pub fn frame(&self) -> usize{
+        self.frame
+    }
+
+    pub fn row(&self) -> usize{
+        self.row
+    }
+
+    /// the period or frequency a column is playing, `None` while it is
+    /// silent or its chip isn't driven
+    pub fn period(&self, column: usize) -> Option<u16>{
+        self.channels.get(column)?.as_ref()?.period(&self.tables)
+    }
+
+    fn current_track(&self) -> Option<&Track>{
+        self.track.and_then(|track| self.file.tracks.get(track))
+    }
+
+    /// Fxx values from here up set the tempo, 32 when the file has no SPLIT
+    fn speed_split(&self) -> u32{
+        match self.file.split{
+            0 => 0x20,
+            split => split,
         }
     }
 
-    fn play_current_row(&mut self){
-        let track = self.current_track.unwrap();
-        let curr_order = &track.pattern_order[self.pattern_order as usize];
-        
-        for i in 0..curr_order.1.len(){
-            let current_order_note_pattern = &track.patterns[curr_order.1[i] as usize];
-            let row = &current_order_note_pattern.rows[self.row as usize];
-            self.play_sheet_note( &row.sheet_notes[i], i as u8);
+    /// a speed of 0 is treated as 1
+    fn setup_speed(&mut self){
+        let speed = self.speed.max(1);
+        if self.tempo > 0{
+            self.tempo_decrement = (self.tempo * 24 / speed) as i32;
+            self.tempo_remainder = (self.tempo * 24 % speed) as i32;
+        }else{
+            self.tempo_decrement = 1;
+            self.tempo_remainder = 0;
         }
     }
 
-    fn play_sheet_note(&mut self, note: &SheetNote, inst: u8){
-        match inst{
-            0 => {
-                //square 1
+    /// one engine tick, rows are read whenever the tempo accumulator runs out
+    pub fn tick(&mut self, board: &mut Board){
+        if self.init_hardware{
+            self.init_hardware = false;
+            board.set_region(self.region);
+            board.set_expansion(self.file.expansion);
+            board.reset();
+            Channel::init(board);
+        }
+        if !self.playing(){
+            return;
+        }
+        if self.tempo_accum <= 0{
+            // a Cxx row plays out and the next row is never read
+            if self.halt{
+                self.stop();
+                return;
             }
-            1 => {
-                //square 2
+            self.play_row(board);
+            self.advance_row();
+            let ticks_per_row = if self.tempo > 0{
+                60 * self.engine_rate().round() as i32
+            }else{
+                self.speed as i32
+            };
+            self.tempo_accum += ticks_per_row - self.tempo_remainder;
+        }
+        self.tempo_accum -= self.tempo_decrement;
+
+        for channel in self.channels.iter_mut().flatten(){
+            channel.refresh(&self.file, &self.tables, board);
+        }
+    }
+
+    fn play_row(&mut self, board: &mut Board){
+        let file = self.file.clone();
+        let track = match self.track.and_then(|track| file.tracks.get(track)){
+            Some(track) => track,
+            None => return,
+        };
+        let order = match track.pattern_order.get(self.frame){
+            Some(order) => order,
+            None => return,
+        };
+
+        for (channel, pattern_id) in order.1.iter().enumerate(){
+            let pattern = track.patterns.iter().find(|pattern| pattern.id == *pattern_id);
+            if let Some(row) = pattern.and_then(|pattern| pattern.rows.get(self.row)){
+                let note = &row.sheet_notes[channel];
+                self.apply_global_effects(note);
+                self.play_sheet_note(board, note, channel);
             }
-            2 => {
-                //triangle 1
+        }
+    }
+
+    /// effects that change the player rather than a channel
+    fn apply_global_effects(&mut self, note: &SheetNote){
+        for effect in note.efx.iter().flatten(){
+            match *effect{
+                // the effect is split at $20 when read, SPLIT moves that point
+                Effect::SpeedOrTempo(speed, tempo) => {
+                    let value = speed.or(tempo).unwrap_or(0) as u32;
+                    if value >= self.speed_split(){
+                        self.tempo = value;
+                    }else if value > 0{
+                        self.speed = value;
+                    }
+                    self.setup_speed();
+                }
+                Effect::JumpToPattern(frame) => self.jump = Some(frame as usize),
+                Effect::SkipFrameStartAtRow(row) => self.skip = Some(row as usize),
+                Effect::Halt => self.halt = true,
+                _ => {}
             }
-            3 => {
-                //nosie 1
+        }
+    }
+
+    fn advance_row(&mut self){
+        let track = match self.current_track(){
+            Some(track) => track,
+            None => return,
+        };
+        let pattern_length = track.pattern_length as usize;
+        let frames = track.pattern_order.len();
+
+        // Bxx picks the frame, Dxx the row, together they pick both
+        if self.jump.is_some() || self.skip.is_some(){
+            self.frame = self.jump.take().unwrap_or(self.frame + 1);
+            self.row = self.skip.take().unwrap_or(0);
+            if self.frame >= frames{
+                self.frame = 0;
             }
-            4 => {
-                //DPCM
+            if self.row >= pattern_length{
+                self.row = 0;
             }
-            _ => {
-                
+            return;
+        }
+
+        self.row += 1;
+        if self.row >= pattern_length{
+            self.row = 0;
+            self.frame += 1;
+            if self.frame >= frames{
+                self.frame = 0;
             }
         }
     }
 
-    pub fn run_frame(&mut self){
-        let asdf = move||{
-            self.play_current_row();
-        };
+    fn play_sheet_note(&mut self, board: &mut Board, note: &SheetNote, channel: usize){
+        if let Some(Some(channel)) = self.channels.get_mut(channel){
+            channel.play_note(&self.file, &self.tables, note, board);
+        }
     }
+}
 
-    pub fn play_frame(&mut self){
-        self.next_row();
-        self.run_frame();
-    }
+#[cfg(test)]
+mod tests{
+    use crate::tests::{apu_module, player};
 
-    pub fn reset(&mut self){
-        self.row = 0;
-        self.pattern_order = 0;
+    #[test]
+    pub fn flow_effects_pick_the_next_row(){
+        let empty: &[&str] = &["... .. . ...", "... .. . ..."];
+        let after_first_row = |cell: &str| {
+            let (mut player, mut board) = player(&apu_module("", 0, &[&[cell, "... .. . ..."], empty, empty]));
+            player.tick(&mut board);
+            (player.frame(), player.row())
+        };
+        assert_eq!(after_first_row("... .. . ..."), (0, 1));
+        assert_eq!(after_first_row("... .. . B02"), (2, 0));
+        assert_eq!(after_first_row("... .. . D01"), (1, 1));
+
+        // Cxx lets its row play out, then stops
+        let (mut player, mut board) = player(&apu_module("", 0, &[&["A-4 .. F C00", "... .. . ..."]]));
+        for _ in 0..6{
+            player.tick(&mut board);
+        }
+        assert!(player.playing());
+        player.tick(&mut board);
+        assert!(!player.playing());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,1173 @@
+use crate::{hardware_interface::Board, sound_file::*};
+
+use super::{period::{PeriodTables, NOTE_OFFSET, NOTE_COUNT}, sequence::{Sequence, MacroType, ArpScheme}};
+
+/// all four non-DMC channels stay enabled, the DMC bit is written on its own
+const CHANNELS_ENABLED: u8 = 0b0000_1111;
+/// channel volume is kept in eighths so Axy can slide it slowly
+const VOLUME_SHIFT: i32 = 3;
+const MAX_VOLUME: i32 = (0x0F << VOLUME_SHIFT) | 0x07;
+/// the 5B mixer with the squares on and the noise off on every channel
+const S5B_NOISE_OFF: u8 = 0b0011_1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelKind{
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dpcm,
+    Vrc6Pulse1,
+    Vrc6Pulse2,
+    Sawtooth,
+    Vrc7(u8),
+    Mmc5Pulse1,
+    Mmc5Pulse2,
+    Fds,
+    /// 0 is the channel whose registers sit at the top of N163 RAM
+    N163(u8),
+    /// the 5B's squares A, B and C
+    S5B(u8),
+}
+
+impl ChannelKind{
+    /// the `index`th column of `chip`, in register order
+    pub fn new(chip: Chip, index: usize) -> Option<Self>{
+        match (chip, index){
+            (Chip::Apu, 0) => Some(ChannelKind::Pulse1),
+            (Chip::Apu, 1) => Some(ChannelKind::Pulse2),
+            (Chip::Apu, 2) => Some(ChannelKind::Triangle),
+            (Chip::Apu, 3) => Some(ChannelKind::Noise),
+            (Chip::Apu, 4) => Some(ChannelKind::Dpcm),
+            (Chip::Vrc6, 0) => Some(ChannelKind::Vrc6Pulse1),
+            (Chip::Vrc6, 1) => Some(ChannelKind::Vrc6Pulse2),
+            (Chip::Vrc6, 2) => Some(ChannelKind::Sawtooth),
+            (Chip::Vrc7, 0..=5) => Some(ChannelKind::Vrc7(index as u8)),
+            (Chip::Mmc5, 0) => Some(ChannelKind::Mmc5Pulse1),
+            (Chip::Mmc5, 1) => Some(ChannelKind::Mmc5Pulse2),
+            (Chip::Fds, 0) => Some(ChannelKind::Fds),
+            (Chip::N163, 0..=7) => Some(ChannelKind::N163(index as u8)),
+            (Chip::S5B, 0..=2) => Some(ChannelKind::S5B(index as u8)),
+            _ => None,
+        }
+    }
+
+    fn chip(&self) -> Chip{
+        match self{
+            ChannelKind::Vrc6Pulse1 | ChannelKind::Vrc6Pulse2 | ChannelKind::Sawtooth => Chip::Vrc6,
+            ChannelKind::Vrc7(_) => Chip::Vrc7,
+            ChannelKind::Mmc5Pulse1 | ChannelKind::Mmc5Pulse2 => Chip::Mmc5,
+            ChannelKind::Fds => Chip::Fds,
+            ChannelKind::N163(_) => Chip::N163,
+            ChannelKind::S5B(_) => Chip::S5B,
+            _ => Chip::Apu,
+        }
+    }
+
+    /// the MMC5 has no instruments of its own and plays 2A03 ones
+    fn instrument_chip(&self) -> Chip{
+        match self.chip(){
+            Chip::Mmc5 => Chip::Apu,
+            chip => chip,
+        }
+    }
+
+    fn base_address(&self) -> u16{
+        match self{
+            ChannelKind::Pulse1 => 0x4000,
+            ChannelKind::Pulse2 => 0x4004,
+            ChannelKind::Triangle => 0x4008,
+            ChannelKind::Noise => 0x400C,
+            ChannelKind::Dpcm => 0x4010,
+            ChannelKind::Vrc6Pulse1 => 0x9000,
+            ChannelKind::Vrc6Pulse2 => 0xA000,
+            ChannelKind::Sawtooth => 0xB000,
+            // the channel's number, its registers are $1x, $2x and $3x
+            ChannelKind::Vrc7(index) => *index as u16,
+            ChannelKind::Mmc5Pulse1 => 0x5000,
+            ChannelKind::Mmc5Pulse2 => 0x5004,
+            ChannelKind::Fds => 0x4080,
+            // an offset into N163 RAM rather than a bus address
+            ChannelKind::N163(index) => 0x78 - 8 * *index as u16,
+            // the channel's number, its period is in registers 2n and 2n+1
+            // and its volume in 8+n
+            ChannelKind::S5B(index) => *index as u16,
+        }
+    }
+
+    /// the VRC6, FDS and 5B have 12 bit timers
+    fn max_period(&self) -> i32{
+        match self.chip(){
+            Chip::Vrc6 | Chip::Fds | Chip::S5B => 0xFFF,
+            Chip::Vrc7 | Chip::N163 => 0xFFFF,
+            _ => 0x7FF,
+        }
+    }
+
+    /// the FDS's volume and its volume macros go up to 32
+    fn max_volume(&self) -> u8{
+        match self.chip(){
+            Chip::Fds => 32,
+            _ => 15,
+        }
+    }
+
+    /// the VRC7, FDS and N163 are given frequencies, so slides and offsets
+    /// move the other way
+    fn inverted(&self) -> bool{
+        matches!(self.chip(), Chip::Vrc7 | Chip::Fds | Chip::N163)
+    }
+
+    /// the VRC6 pulses have eight duty settings, the sawtooth uses the low
+    /// bit, on the N163 it picks a wave, on the 5B it is a mode, see
+    /// `refresh_s5b`
+    fn duty_mask(&self) -> u8{
+        match self.chip(){
+            Chip::Vrc6 => 0x07,
+            Chip::N163 | Chip::S5B => 0xFF,
+            _ => 0x03,
+        }
+    }
+
+    fn is_pulse(&self) -> bool{
+        matches!(self, ChannelKind::Pulse1 | ChannelKind::Pulse2)
+    }
+
+    /// channels whose pitch is a timer period that slides and vibrato move
+    fn is_tonal(&self) -> bool{
+        !matches!(self, ChannelKind::Noise | ChannelKind::Dpcm)
+    }
+}
+
+/// The effect that owns the channel's period, only one runs at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PitchEffect{
+    None,
+    Arpeggio(u8, u8),
+    SlideUp(u8),
+    SlideDown(u8),
+    Portamento(u8),
+    /// Qxy/Rxy, glides to the note then stops
+    NoteSlide(u8),
+}
+
+/// 4xy and 7xy share a 64 step sine
+#[derive(Debug, Clone, Copy, Default)]
+struct Oscillator{
+    speed: u8,
+    depth: u8,
+    phase: u8,
+}
+
+impl Oscillator{
+    fn set(&mut self, param: Option<(u8, u8)>){
+        let (speed, depth) = param.unwrap_or((0, 0));
+        self.speed = speed;
+        self.depth = depth;
+    }
+
+    fn step(&mut self){
+        self.phase = (self.phase + self.speed) & 0x3F;
+    }
+}
+
+/// What one track column is currently playing.
+#[derive(Debug)]
+pub struct Channel{
+    kind: ChannelKind,
+    /// MIDI note, or the 0-F period index on the noise channel
+    note: Option<i32>,
+    instrument: Option<u8>,
+    /// in eighths, see `VOLUME_SHIFT`
+    volume: i32,
+    duty: u8,
+    active: bool,
+    /// the instrument's macros, indexed by `MacroType`
+    sequences: [Option<Sequence>; 5],
+    seq_volume: u8,
+    /// a fixed arpeggio is overriding the period
+    arp_fixed: bool,
+    /// timer period before pitch macros, vibrato and fine pitch
+    period: i32,
+    /// accumulated pitch and hi-pitch macro offset, in timer periods
+    pitch_offset: i32,
+    pitch_effect: PitchEffect,
+    arp_step: u8,
+    vibrato: Oscillator,
+    tremolo: Oscillator,
+    /// eighths of volume added every tick
+    volume_slide: i32,
+    /// Pxx, in timer periods
+    fine_pitch: i32,
+    /// ticks until Sxx cuts the note
+    cut_delay: Option<u8>,
+    /// a row held back by Gxx and the ticks left before it plays
+    delayed: Option<(SheetNote, u8)>,
+    /// $4001/$4005 value from Hxy/Ixy, used by the next note
+    sweep: Option<u8>,
+    /// the hardware sweep owns the period until the next note
+    sweeping: bool,
+    /// DMC rate of the sample playing, kept so a release can drop its loop
+    dpcm_pitch: u8,
+    /// Wxx, replaces the rate of the next sample
+    dpcm_pitch_override: Option<u8>,
+    /// Yxx, in 64 byte steps, applies to the next sample
+    dpcm_offset: u8,
+    /// Xxx, the retrigger interval and the ticks left
+    dpcm_retrigger: Option<(u8, u8)>,
+    /// writing a pulse's high period byte restarts its waveform, so it is
+    /// only written when it changes
+    last_period_high: Option<u8>,
+    /// FDS modulation from the instrument
+    fds_mod_depth: u8,
+    fds_mod_speed: u16,
+    /// ticks the modulator stays halted after a note
+    fds_mod_delay: u8,
+    /// Hxx, Ixx and Jxx, they override the instrument until the channel resets
+    fds_effect_depth: Option<u8>,
+    fds_effect_speed_high: Option<u8>,
+    fds_effect_speed_low: Option<u8>,
+    /// a note restarts the modulation counter
+    fds_reset_mod: bool,
+    /// the instrument whose wave and modulation table the FDS holds
+    fds_loaded: Option<u8>,
+    /// the wave this N163 channel last wrote to RAM
+    n163_wave: Option<usize>,
+    /// the VRC7 block the period is an F-Num in, set when a note starts
+    vrc7_block: u8,
+    /// the key is written off before it goes back on, restarting the envelopes
+    vrc7_retrigger: bool,
+    /// a release lets the note fade with the sustain bit set
+    vrc7_released: bool,
+    /// the block and F-Num high bit last written, a cut keys off with them
+    vrc7_high: u8,
+}
+
+impl Channel{
+    pub fn new(kind: ChannelKind) -> Self{
+        Self {
+            kind,
+            note: None,
+            instrument: None,
+            volume: MAX_VOLUME,
+            duty: 0,
+            active: false,
+            sequences: Default::default(),
+            seq_volume: 0x0F,
+            arp_fixed: false,
+            period: 0,
+            pitch_offset: 0,
+            pitch_effect: PitchEffect::None,
+            arp_step: 0,
+            vibrato: Default::default(),
+            tremolo: Default::default(),
+            volume_slide: 0,
+            fine_pitch: 0,
+            cut_delay: None,
+            delayed: None,
+            sweep: None,
+            sweeping: false,
+            dpcm_pitch: 0,
+            dpcm_pitch_override: None,
+            dpcm_offset: 0,
+            dpcm_retrigger: None,
+            last_period_high: None,
+            fds_mod_depth: 0,
+            fds_mod_speed: 0,
+            fds_mod_delay: 0,
+            fds_effect_depth: None,
+            fds_effect_speed_high: None,
+            fds_effect_speed_low: None,
+            fds_reset_mod: false,
+            fds_loaded: None,
+            n163_wave: None,
+            vrc7_block: 0,
+            vrc7_retrigger: false,
+            vrc7_released: false,
+            vrc7_high: 0,
+        }
+    }
+
+    /// sets the registers the engine never touches again, the sweep units
+    /// are disabled with negate set so low notes are not muted
+    pub fn init(board: &mut Board){
+        board.write_register(0x4015, CHANNELS_ENABLED);
+        board.write_register(0x4017, 0x40);
+        board.write_register(0x4001, 0x08);
+        board.write_register(0x4005, 0x08);
+        board.write_register(0x9003, 0x00);
+        board.write_register(0x5015, 0x03);
+        Self::write_s5b(board, 0x07, S5B_NOISE_OFF);
+    }
+
+    /// reads a row, holding it back if it carries a note delay
+    pub fn play_note(&mut self, file: &SoundFile, tables: &PeriodTables, note: &SheetNote, board: &mut Board){
+        // a delayed row still waiting when the next one arrives plays first
+        if let Some((delayed, _)) = self.delayed.take(){
+            self.play_row(file, tables, &delayed, board);
+        }
+        let delay = note.efx.iter().flatten().find_map(|effect| match *effect{
+            Effect::NoteDelay(ticks) if ticks > 0 => Some(ticks),
+            _ => None,
+        });
+        match delay{
+            Some(ticks) => self.delayed = Some((note.clone(), ticks)),
+            None => self.play_row(file, tables, note, board),
+        }
+    }
+
+    /// effects go first so portamento, sweeps and sample overrides see the
+    /// note, note slides are then aimed from it
+    fn play_row(&mut self, file: &SoundFile, tables: &PeriodTables, note: &SheetNote, board: &mut Board){
+        if let Some(inst) = note.inst{
+            self.instrument = Some(inst);
+        }
+        if let Some(vol) = note.vol{
+            self.volume = (vol as i32) << VOLUME_SHIFT;
+        }
+        for effect in note.efx.iter().flatten(){
+            self.apply_effect(tables, *effect, board);
+        }
+        match note.note{
+            Some(Note::Midi(midi)) => {
+                let note = match self.kind{
+                    ChannelKind::Noise => ((midi - NOTE_OFFSET) & 0x0F) as i32,
+                    _ => midi as i32,
+                };
+                self.trigger(file, tables, note, board);
+            }
+            Some(Note::Hex(index)) => self.trigger(file, tables, index as i32, board),
+            Some(Note::Cut) => self.cut(board),
+            Some(Note::Release) => self.release(board),
+            None => {}
+        }
+        for effect in note.efx.iter().flatten(){
+            match *effect{
+                Effect::NoteSlideUp(speed, semitones) => self.note_slide(speed, semitones as i32),
+                Effect::NoteSlideDown(speed, semitones) => self.note_slide(speed, -(semitones as i32)),
+                _ => {}
+            }
+        }
+    }
+
+    /// stores an effect's state, the per tick work happens in `run_effects`
+    fn apply_effect(&mut self, tables: &PeriodTables, effect: Effect, board: &mut Board){
+        match effect{
+            Effect::Arpeggio(0, 0) => {
+                if let PitchEffect::Arpeggio(..) = self.pitch_effect{
+                    self.pitch_effect = PitchEffect::None;
+                    // the arpeggio may have stopped on x or y
+                    if let Some(note) = self.note{
+                        self.period = self.note_period(tables, note);
+                    }
+                }
+            }
+            Effect::Arpeggio(x, y) => {
+                self.pitch_effect = PitchEffect::Arpeggio(x, y);
+                self.arp_step = 0;
+            }
+            Effect::PitchSlideUp(speed) | Effect::PitchSlideDown(speed) => {
+                self.pitch_effect = match (effect, speed){
+                    (Effect::PitchSlideUp(_), Some(speed)) => PitchEffect::SlideUp(speed),
+                    (_, Some(speed)) => PitchEffect::SlideDown(speed),
+                    (_, None) => match self.pitch_effect{
+                        PitchEffect::SlideUp(_) | PitchEffect::SlideDown(_) => PitchEffect::None,
+                        other => other,
+                    },
+                };
+            }
+            Effect::AutomaticPortamento(speed) => {
+                self.pitch_effect = match speed{
+                    Some(speed) => PitchEffect::Portamento(speed),
+                    None if matches!(self.pitch_effect, PitchEffect::Portamento(_)) => PitchEffect::None,
+                    None => self.pitch_effect,
+                };
+            }
+            Effect::VibratoEffect(param) => self.vibrato.set(param),
+            Effect::TremoloEffect(param) => self.tremolo.set(param),
+            Effect::VolumeSlide(up, amount) => {
+                self.volume_slide = if up { amount as i32 } else { -(amount as i32) };
+            }
+            // the old Exx sets the volume like the volume column does
+            Effect::Volume(volume) => self.volume = (volume.min(0x0F) as i32) << VOLUME_SHIFT,
+            Effect::FinePitch(pitch) => self.fine_pitch = 0x80 - pitch as i32,
+            Effect::MuteDelay(ticks) => self.cut_delay = Some(ticks),
+            Effect::HardwareSweepUp(speed, shift) if self.kind.is_pulse() => {
+                self.sweep = Some(0x88 | ((speed & 0x07) << 4) | (shift & 0x07));
+            }
+            Effect::HardwareSweepDown(speed, shift) if self.kind.is_pulse() => {
+                self.sweep = Some(0x80 | ((speed & 0x07) << 4) | (shift & 0x07));
+            }
+            Effect::FSDModulationDepth(depth) => self.fds_effect_depth = Some(depth & 0x3F),
+            Effect::FDSModulationSpeedHigh(speed) => self.fds_effect_speed_high = Some(speed & 0x0F),
+            Effect::FDSModulationSpeedLow(speed) => self.fds_effect_speed_low = Some(speed),
+            Effect::SunsoftEnvelopeLow(period) if self.kind.chip() == Chip::S5B => {
+                Self::write_s5b(board, 0x0B, period);
+            }
+            Effect::SunsoftEnvelopeHigh(period) if self.kind.chip() == Chip::S5B => {
+                Self::write_s5b(board, 0x0C, period);
+            }
+            // writing the shape restarts the envelope
+            Effect::SunsoftEnvelopeShape(shape) if self.kind.chip() == Chip::S5B => {
+                Self::write_s5b(board, 0x0D, shape & 0x0F);
+            }
+            Effect::AquareDuityNoiseN163Mode(duty) => self.duty = duty & self.kind.duty_mask(),
+            Effect::DPCMSampleSpeedOverride(pitch) => self.dpcm_pitch_override = Some(pitch & 0x0F),
+            Effect::DPCMSampleOffset(offset) => self.dpcm_offset = (offset / 64).min(0xFF) as u8,
+            Effect::DPCMDeltaCounter(delta) if self.kind == ChannelKind::Dpcm => {
+                board.write_register(0x4011, delta & 0x7F);
+            }
+            Effect::DPCMRetrigger(ticks) if self.kind == ChannelKind::Dpcm => {
+                self.dpcm_retrigger = if ticks > 0 { Some((ticks, ticks)) } else { None };
+            }
+            _ => {}
+        }
+    }
+
+    /// Qxy/Rxy move the note now and let the period glide after it
+    fn note_slide(&mut self, speed: u8, semitones: i32){
+        let note = match self.note{
+            Some(note) if self.kind.is_tonal() => note,
+            _ => return,
+        };
+        self.note = Some(Self::clamp_note(note + semitones));
+        self.pitch_effect = PitchEffect::NoteSlide(speed * 2 + 1);
+    }
+
+    fn trigger(&mut self, file: &SoundFile, tables: &PeriodTables, note: i32, board: &mut Board){
+        // portamento glides from the note already playing
+        let glide = matches!(self.pitch_effect, PitchEffect::Portamento(_))
+            && self.active
+            && self.note.is_some();
+        if let PitchEffect::NoteSlide(_) = self.pitch_effect{
+            self.pitch_effect = PitchEffect::None;
+        }
+        self.note = Some(note);
+        if !glide{
+            if let ChannelKind::Vrc7(_) = self.kind{
+                self.vrc7_block = tables.vrc7_block(note);
+            }
+            self.period = self.note_period(tables, note);
+        }
+        self.active = true;
+        self.arp_step = 0;
+        self.load_sequences(file);
+        match self.kind{
+            ChannelKind::Pulse1 | ChannelKind::Pulse2 => {
+                self.last_period_high = None;
+                self.trigger_sweep(board);
+            }
+            ChannelKind::Mmc5Pulse1 | ChannelKind::Mmc5Pulse2 => self.last_period_high = None,
+            ChannelKind::Fds => self.trigger_fds(file, board),
+            // the instrument may have changed, so its wave is written again
+            ChannelKind::N163(_) => self.n163_wave = None,
+            ChannelKind::Vrc7(_) => self.trigger_vrc7(file, board),
+            ChannelKind::Noise => {
+                board.write_register(0x400F, 0x00);
+            }
+            ChannelKind::Dpcm => self.trigger_dpcm(file, note, board),
+            _ => {}
+        }
+    }
+
+    /// a pending Hxy/Ixy hands the period to the sweep unit, a note
+    /// without one takes it back
+    fn trigger_sweep(&mut self, board: &mut Board){
+        let address = self.kind.base_address() + 1;
+        match self.sweep.take(){
+            Some(sweep) if sweep & 0x07 != 0 => {
+                board.write_register(address, sweep);
+                self.sweeping = true;
+            }
+            _ if self.sweeping => {
+                board.write_register(address, 0x08);
+                self.sweeping = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// timer period of a note, the noise channel's "period" is its index
+    fn note_period(&self, tables: &PeriodTables, note: i32) -> i32{
+        match self.kind{
+            ChannelKind::Pulse1 | ChannelKind::Pulse2 | ChannelKind::Mmc5Pulse1 | ChannelKind::Mmc5Pulse2 => {
+                tables.pulse(note) as i32
+            }
+            ChannelKind::Triangle => tables.triangle(note) as i32,
+            ChannelKind::Noise => note & 0x0F,
+            ChannelKind::Dpcm => 0,
+            ChannelKind::Vrc6Pulse1 | ChannelKind::Vrc6Pulse2 => tables.vrc6_pulse(note) as i32,
+            ChannelKind::Sawtooth => tables.sawtooth(note) as i32,
+            ChannelKind::Vrc7(_) => (tables.vrc7(note) >> self.vrc7_block) as i32,
+            ChannelKind::Fds => tables.fds(note) as i32,
+            ChannelKind::N163(_) => tables.n163(note) as i32,
+            ChannelKind::S5B(_) => tables.s5b(note) as i32,
+        }
+    }
+
+    fn clamp_note(note: i32) -> i32{
+        note.clamp(NOTE_OFFSET as i32, NOTE_OFFSET as i32 + NOTE_COUNT as i32 - 1)
+    }
+
+    /// restarts the instrument's macros, resolving each by type and id
+    fn load_sequences(&mut self, file: &SoundFile){
+        self.seq_volume = self.kind.max_volume();
+        self.arp_fixed = false;
+        self.pitch_offset = 0;
+        self.sequences = Default::default();
+
+        // instruments of other chips are ignored, as FamiTracker does
+        let inst = match self.instrument.and_then(|id| file.instrument(id)){
+            Some(inst) if inst.chip() == self.kind.instrument_chip() => inst,
+            _ => return,
+        };
+        // FDS instruments carry their volume, arpeggio and pitch macros
+        if let Instrument::Fds(inst) = inst{
+            for song_macro in inst.macros.iter(){
+                if let Some(sequence) = self.sequences.get_mut(song_macro.m_type as usize){
+                    *sequence = Some(Sequence::new(song_macro));
+                }
+            }
+            return;
+        }
+        let ids = match inst.macro_ids(){
+            Some(ids) => ids,
+            None => return,
+        };
+        for (macro_type, id) in MacroType::ALL.iter().zip(ids){
+            self.sequences[*macro_type as usize] = id.and_then(|id| {
+                file.macros
+                    .iter()
+                    .find(|song_macro| {
+                        song_macro.chip == inst.chip() && song_macro.m_type == *macro_type as u8 && song_macro.m_id == id
+                    })
+                    .map(Sequence::new)
+            });
+        }
+    }
+
+    /// advances every macro by one tick and applies its value
+    fn run_sequences(&mut self, tables: &PeriodTables){
+        for macro_type in MacroType::ALL{
+            let sequence = match self.sequences[macro_type as usize].as_mut(){
+                Some(sequence) => sequence,
+                None => continue,
+            };
+            let scheme = sequence.arp_scheme();
+            let value = match sequence.step(){
+                Some(value) => value as i32,
+                None => {
+                    // the played note comes back once a fixed arpeggio ends
+                    if macro_type == MacroType::Arpeggio && self.arp_fixed{
+                        self.arp_fixed = false;
+                        if let Some(note) = self.note{
+                            self.period = self.note_period(tables, note);
+                        }
+                    }
+                    continue;
+                }
+            };
+            match macro_type{
+                MacroType::Volume => self.seq_volume = value.clamp(0, self.kind.max_volume() as i32) as u8,
+                MacroType::Arpeggio => self.run_arpeggio(tables, scheme, value),
+                MacroType::Pitch => self.pitch_offset += value,
+                MacroType::HiPitch => self.pitch_offset += value * 16,
+                MacroType::Duty => self.duty = value as u8 & self.kind.duty_mask(),
+            }
+        }
+    }
+
+    fn run_arpeggio(&mut self, tables: &PeriodTables, scheme: ArpScheme, value: i32){
+        let note = match self.note{
+            Some(note) => note,
+            None => return,
+        };
+        let note = match scheme{
+            ArpScheme::Absolute => note + value,
+            ArpScheme::Fixed => {
+                self.arp_fixed = true;
+                // fixed values on the noise channel are noise periods, not notes
+                match self.kind{
+                    ChannelKind::Noise => value & 0x0F,
+                    _ => value + NOTE_OFFSET as i32,
+                }
+            }
+            ArpScheme::Relative => {
+                let note = match self.kind{
+                    ChannelKind::Noise => (note + value) & 0x0F,
+                    _ => Self::clamp_note(note + value),
+                };
+                self.note = Some(note);
+                note
+            }
+        };
+        self.period = self.note_period(tables, note);
+    }
+
+    /// one tick of the effects set by `apply_effect`
+    fn run_effects(&mut self, tables: &PeriodTables){
+        let note = self.note.unwrap_or(0);
+        match self.pitch_effect{
+            PitchEffect::None => {}
+            PitchEffect::Arpeggio(x, y) => {
+                let offset = match self.arp_step{
+                    0 => 0,
+                    1 => x,
+                    _ => y,
+                };
+                self.arp_step = if self.arp_step >= 2 || (self.arp_step == 1 && y == 0) { 0 } else { self.arp_step + 1 };
+                let note = match self.kind{
+                    ChannelKind::Noise => (note + offset as i32) & 0x0F,
+                    _ => Self::clamp_note(note + offset as i32),
+                };
+                self.period = self.note_period(tables, note);
+            }
+            _ if !self.kind.is_tonal() => {}
+            PitchEffect::SlideUp(speed) => self.slide(-(speed as i32)),
+            PitchEffect::SlideDown(speed) => self.slide(speed as i32),
+            PitchEffect::Portamento(speed) | PitchEffect::NoteSlide(speed) => {
+                let target = self.note_period(tables, note);
+                let speed = speed as i32;
+                self.period = if self.period > target{
+                    (self.period - speed).max(target)
+                }else{
+                    (self.period + speed).min(target)
+                };
+                if self.period == target && matches!(self.pitch_effect, PitchEffect::NoteSlide(_)){
+                    self.pitch_effect = PitchEffect::None;
+                }
+            }
+        }
+        self.vibrato.step();
+        self.tremolo.step();
+        self.volume = (self.volume + self.volume_slide).clamp(0, MAX_VOLUME);
+    }
+
+    /// moves the period by `change`, which lowers the pitch when positive
+    fn slide(&mut self, change: i32){
+        let change = if self.kind.inverted() { -change } else { change };
+        self.period = (self.period + change).clamp(0, self.kind.max_period());
+    }
+
+    /// counts down Gxx, Sxx and Xxx
+    fn run_delays(&mut self, file: &SoundFile, tables: &PeriodTables, board: &mut Board){
+        if let Some((note, ticks)) = self.delayed.as_mut(){
+            if *ticks == 0{
+                let note = note.clone();
+                self.delayed = None;
+                self.play_row(file, tables, &note, board);
+            }else{
+                *ticks -= 1;
+            }
+        }
+        if let Some(ticks) = self.cut_delay.as_mut(){
+            if *ticks == 0{
+                self.cut_delay = None;
+                self.cut(board);
+            }else{
+                *ticks -= 1;
+            }
+        }
+        // the count starts on the tick the note plays, so restarts land
+        // every `interval` ticks from it
+        if let Some((interval, ticks)) = self.dpcm_retrigger.as_mut(){
+            let due = *ticks == 0;
+            if due{
+                *ticks = *interval;
+            }
+            *ticks -= 1;
+            if let (true, true, Some(note)) = (due, self.active, self.note){
+                self.trigger_dpcm(file, note, board);
+            }
+        }
+    }
+
+    /// channel volume scaled by the volume macro and lowered by tremolo,
+    /// never rounding an audible note down to silence
+    fn output_volume(&self, tables: &PeriodTables) -> u8{
+        let channel = self.volume >> VOLUME_SHIFT;
+        let tremolo = if self.tremolo.speed > 0{
+            // tremolo only ever lowers the volume, so both halves of the sine
+            // dip it and it repeats twice as fast as vibrato
+            tables.oscillator(self.tremolo.depth, self.tremolo.phase).abs() >> 1
+        }else{
+            0
+        };
+        let volume = (channel * self.seq_volume as i32 / 15 - tremolo).clamp(0, self.kind.max_volume() as i32);
+        if volume == 0 && channel > 0 && self.seq_volume > 0{
+            1
+        }else{
+            volume as u8
+        }
+    }
+
+    /// a custom patch is loaded into $00-$07, shared by every channel
+    fn trigger_vrc7(&mut self, file: &SoundFile, board: &mut Board){
+        self.vrc7_retrigger = true;
+        self.vrc7_released = false;
+        if let Some(Instrument::Vrc7(inst)) = self.instrument.and_then(|id| file.instrument(id)){
+            if inst.patch == 0{
+                for (register, value) in inst.custom.iter().enumerate(){
+                    Self::write_vrc7(board, register as u8, *value);
+                }
+            }
+        }
+    }
+
+    fn write_vrc7(board: &mut Board, register: u8, value: u8){
+        board.write_register(0x9010, register);
+        board.write_register(0x9030, value);
+    }
+
+    /// keys the note on, or off with the sustain bit once released, the
+    /// period is moved to whichever block fits it in a 9 bit F-Num
+    fn refresh_vrc7(&mut self, file: &SoundFile, tables: &PeriodTables, volume: u8, board: &mut Board){
+        let channel = self.kind.base_address() as u8;
+        let patch = match self.instrument.and_then(|id| file.instrument(id)){
+            Some(Instrument::Vrc7(inst)) => inst.patch,
+            _ => {
+                Self::write_vrc7(board, 0x20 + channel, self.vrc7_high);
+                return;
+            }
+        };
+        let mut fnum = (self.final_period(tables) as u32) << self.vrc7_block;
+        let mut block = 0;
+        while fnum > 0x1FF && block < 7{
+            fnum >>= 1;
+            block += 1;
+        }
+        let fnum = fnum.min(0x1FF);
+        self.vrc7_high = (block << 1) | (fnum >> 8) as u8;
+
+        if std::mem::take(&mut self.vrc7_retrigger){
+            Self::write_vrc7(board, 0x20 + channel, self.vrc7_high);
+        }
+        Self::write_vrc7(board, 0x30 + channel, (patch << 4) | (0x0F - volume));
+        Self::write_vrc7(board, 0x10 + channel, fnum as u8);
+        let key = if self.vrc7_released { 0x20 } else { 0x10 };
+        Self::write_vrc7(board, 0x20 + channel, key | self.vrc7_high);
+    }
+
+    /// takes the modulation from the instrument, loading its wave and
+    /// modulation table if another instrument's are in the FDS
+    fn trigger_fds(&mut self, file: &SoundFile, board: &mut Board){
+        self.fds_reset_mod = true;
+        let inst = match self.instrument.and_then(|id| file.instrument(id)){
+            Some(Instrument::Fds(inst)) => inst,
+            _ => return,
+        };
+        self.fds_mod_depth = if inst.mod_enable { inst.mod_depth & 0x3F } else { 0 };
+        self.fds_mod_speed = inst.mod_speed & 0xFFF;
+        self.fds_mod_delay = inst.mod_delay;
+        if self.fds_loaded == self.instrument{
+            return;
+        }
+        self.fds_loaded = self.instrument;
+        board.write_register(0x4089, 0x80);
+        for (address, sample) in (0x4040..).zip(inst.wave.iter()){
+            board.write_register(address, *sample);
+        }
+        board.write_register(0x4089, 0x00);
+        board.write_register(0x4087, 0x80);
+        for entry in inst.modulation.iter(){
+            board.write_register(0x4088, *entry);
+        }
+    }
+
+    /// plays the sample the instrument assigns to `note`
+    fn trigger_dpcm(&mut self, file: &SoundFile, note: i32, board: &mut Board){
+        let key = file.keydpcm.iter().find(|key| {
+            Some(key.inst_id) == self.instrument && key.midi_note as i32 == note
+        });
+        let (key, sample) = match key.and_then(|key| {
+            file.dpcmdef.iter().find(|sample| sample.id == key.dpcm_id).map(|sample| (key, sample))
+        }){
+            Some(found) => found,
+            None => {
+                self.cut(board);
+                return;
+            }
+        };
+
+        self.dpcm_pitch = self.dpcm_pitch_override.take().unwrap_or(key.pitch) & 0x0F;
+        let offset = std::mem::take(&mut self.dpcm_offset);
+        let length = (sample.data.len().saturating_sub(1) / 16).saturating_sub(offset as usize * 4);
+        board.write_register(0x4015, CHANNELS_ENABLED);
+        board.set_dpcm_sample(Some(sample.data.clone()));
+        board.write_register(0x4010, ((key.loop_key as u8) << 6) | self.dpcm_pitch);
+        board.write_register(0x4012, offset);
+        board.write_register(0x4013, length.min(0xFF) as u8);
+        if let Some(delta) = key.d_counter{
+            board.write_register(0x4011, delta);
+        }
+        board.write_register(0x4015, CHANNELS_ENABLED | 0b0001_0000);
+    }
+
+    /// macros move past their release points, a looping sample stops
+    /// looping and plays out, a VRC7 note fades out
+    fn release(&mut self, board: &mut Board){
+        if let ChannelKind::Vrc7(_) = self.kind{
+            self.vrc7_released = true;
+        }
+        for sequence in self.sequences.iter_mut().flatten(){
+            sequence.release();
+        }
+        if self.kind == ChannelKind::Dpcm && self.active{
+            board.write_register(0x4010, self.dpcm_pitch);
+        }
+    }
+
+    fn cut(&mut self, board: &mut Board){
+        self.active = false;
+        if self.kind == ChannelKind::Dpcm{
+            self.dpcm_retrigger = None;
+            board.write_register(0x4015, CHANNELS_ENABLED);
+        }
+    }
+
+    /// runs one tick and writes the channel's state to the APU
+    pub fn refresh(&mut self, file: &SoundFile, tables: &PeriodTables, board: &mut Board){
+        let base = self.kind.base_address();
+        self.run_delays(file, tables, board);
+        if self.active{
+            self.run_sequences(tables);
+            self.run_effects(tables);
+        }
+        if !self.active || self.note.is_none(){
+            match self.kind{
+                ChannelKind::Pulse1 | ChannelKind::Pulse2 | ChannelKind::Noise
+                | ChannelKind::Mmc5Pulse1 | ChannelKind::Mmc5Pulse2 => {
+                    board.write_register(base, 0x30);
+                }
+                ChannelKind::Triangle => board.write_register(base, 0x80),
+                ChannelKind::Dpcm => {}
+                ChannelKind::Vrc6Pulse1 | ChannelKind::Vrc6Pulse2 | ChannelKind::Sawtooth => {
+                    board.write_register(base, 0x00);
+                }
+                ChannelKind::Fds => {
+                    board.write_register(0x4080, 0x80);
+                    board.write_register(0x4083, 0x80);
+                }
+                ChannelKind::N163(_) => self.silence_n163(file, board),
+                // a cut lets the note ring out at its release rate
+                ChannelKind::Vrc7(index) => Self::write_vrc7(board, 0x20 + index, self.vrc7_high),
+                ChannelKind::S5B(index) => Self::write_s5b(board, 0x08 + index, 0x00),
+            }
+            return;
+        }
+
+        let volume = self.output_volume(tables);
+        match self.kind{
+            ChannelKind::Pulse1 | ChannelKind::Pulse2 | ChannelKind::Mmc5Pulse1 | ChannelKind::Mmc5Pulse2 => {
+                let period = self.final_period(tables);
+                let high = (period >> 8) as u8;
+                board.write_register(base, (self.duty << 6) | 0x30 | volume);
+                // a running sweep owns the period after the note's first write
+                if !self.sweeping || self.last_period_high.is_none(){
+                    board.write_register(base + 2, period as u8);
+                    if self.last_period_high != Some(high){
+                        board.write_register(base + 3, high);
+                        self.last_period_high = Some(high);
+                    }
+                }
+            }
+            ChannelKind::Triangle => {
+                let period = self.final_period(tables);
+                board.write_register(base, if volume > 0 { 0x81 } else { 0x80 });
+                board.write_register(base + 2, period as u8);
+                board.write_register(base + 3, (period >> 8) as u8);
+            }
+            ChannelKind::Noise => {
+                let mode = (self.duty & 1) << 7;
+                board.write_register(base, 0x30 | volume);
+                board.write_register(base + 2, mode | (0x0F - self.final_period(tables) as u8));
+            }
+            ChannelKind::Dpcm => {}
+            ChannelKind::Vrc6Pulse1 | ChannelKind::Vrc6Pulse2 => {
+                let period = self.final_period(tables);
+                board.write_register(base, (self.duty << 4) | volume);
+                board.write_register(base + 1, period as u8);
+                board.write_register(base + 2, 0x80 | (period >> 8) as u8);
+            }
+            ChannelKind::Sawtooth => {
+                // the duty bit picks the upper half of the 6 bit accumulator rate
+                let period = self.final_period(tables);
+                board.write_register(base, ((self.duty & 1) << 5) | (volume << 1));
+                board.write_register(base + 1, period as u8);
+                board.write_register(base + 2, 0x80 | (period >> 8) as u8);
+            }
+            ChannelKind::Fds => self.refresh_fds(tables, volume, board),
+            ChannelKind::N163(_) => self.refresh_n163(file, tables, volume, board),
+            ChannelKind::Vrc7(_) => self.refresh_vrc7(file, tables, volume, board),
+            ChannelKind::S5B(_) => self.refresh_s5b(tables, volume, board),
+        }
+    }
+
+    fn write_s5b(board: &mut Board, register: u8, value: u8){
+        board.write_register(0xC000, register);
+        board.write_register(0xE000, value);
+    }
+
+    /// the duty holds the noise period in its low 5 bits, 0x20 switches
+    /// the volume to the hardware envelope, 0x40 turns the square off and
+    /// 0x80 turns the noise on, each channel owns its two bits of $07
+    fn refresh_s5b(&mut self, tables: &PeriodTables, volume: u8, board: &mut Board){
+        let channel = self.kind.base_address() as u8;
+        let period = self.final_period(tables);
+        Self::write_s5b(board, channel * 2, period as u8);
+        Self::write_s5b(board, channel * 2 + 1, (period >> 8) as u8);
+        let envelope = if self.duty & 0x20 != 0 { 0x10 } else { 0x00 };
+        Self::write_s5b(board, 0x08 + channel, envelope | volume);
+
+        let noise = self.duty & 0x80 != 0;
+        if noise{
+            Self::write_s5b(board, 0x06, self.duty & 0x1F);
+        }
+        let mut mixer = board.s5b.as_ref().map_or(S5B_NOISE_OFF, |s5b| s5b.register(0x07));
+        mixer &= !(0x09 << channel);
+        if self.duty & 0x40 != 0{
+            mixer |= 0x01 << channel;
+        }
+        if !noise{
+            mixer |= 0x08 << channel;
+        }
+        Self::write_s5b(board, 0x07, mixer);
+    }
+
+    fn write_n163(board: &mut Board, address: u16, value: u8){
+        board.write_register(0xF800, address as u8 & 0x7F);
+        board.write_register(0x4800, value);
+    }
+
+    /// every channel writes the channel count that shares $7F with the
+    /// first channel's volume
+    fn silence_n163(&self, file: &SoundFile, board: &mut Board){
+        let channels = file.n163_channels.clamp(1, 8) as u8;
+        Self::write_n163(board, self.kind.base_address() + 7, (channels - 1) << 4);
+    }
+
+    /// loads the wave picked by Vxx or the wave macro when it changes, the
+    /// frequency scales with the channel count and wave length
+    fn refresh_n163(&mut self, file: &SoundFile, tables: &PeriodTables, volume: u8, board: &mut Board){
+        let inst = match self.instrument.and_then(|id| file.instrument(id)){
+            Some(Instrument::N163(inst)) if !inst.waves.is_empty() => inst,
+            _ => {
+                self.silence_n163(file, board);
+                return;
+            }
+        };
+        let base = self.kind.base_address();
+        let wave = (self.duty as usize).min(inst.waves.len() - 1);
+        if self.n163_wave != Some(wave){
+            self.n163_wave = Some(wave);
+            let start = inst.wave_pos as u16 / 2;
+            for (address, pair) in (start..).zip(inst.waves[wave].chunks(2)){
+                let high = pair.get(1).copied().unwrap_or(0);
+                Self::write_n163(board, address, (pair[0] & 0x0F) | (high << 4));
+            }
+        }
+
+        let channels = file.n163_channels.clamp(1, 8);
+        let length = (inst.wave_size as u32 & 0xFC).max(4);
+        let frequency = (self.final_period(tables) as u32 * channels * length / 16).min(0x3FFFF);
+        Self::write_n163(board, base, frequency as u8);
+        Self::write_n163(board, base + 2, (frequency >> 8) as u8);
+        Self::write_n163(board, base + 4, (256 - length) as u8 | (frequency >> 16) as u8);
+        Self::write_n163(board, base + 6, inst.wave_pos);
+        Self::write_n163(board, base + 7, ((channels as u8 - 1) << 4) | volume);
+    }
+
+    /// the volume envelope is bypassed, the modulator waits out the
+    /// instrument's delay halted
+    fn refresh_fds(&mut self, tables: &PeriodTables, volume: u8, board: &mut Board){
+        let period = self.final_period(tables);
+        board.write_register(0x4082, period as u8);
+        board.write_register(0x4083, (period >> 8) as u8);
+        board.write_register(0x4080, 0x80 | volume);
+        if std::mem::take(&mut self.fds_reset_mod){
+            board.write_register(0x4085, 0x00);
+        }
+        if self.fds_mod_delay > 0{
+            self.fds_mod_delay -= 1;
+            board.write_register(0x4087, 0x80);
+            return;
+        }
+        let depth = self.fds_effect_depth.unwrap_or(self.fds_mod_depth);
+        let mut speed = self.fds_mod_speed;
+        if let Some(high) = self.fds_effect_speed_high{
+            speed = (speed & 0x0FF) | ((high as u16) << 8);
+        }
+        if let Some(low) = self.fds_effect_speed_low{
+            speed = (speed & 0xF00) | low as u16;
+        }
+        board.write_register(0x4086, speed as u8);
+        board.write_register(0x4087, (speed >> 8) as u8);
+        board.write_register(0x4084, 0x80 | depth);
+    }
+
+    /// what `refresh` last wrote as the period, `None` while silent
+    pub fn period(&self, tables: &PeriodTables) -> Option<u16>{
+        (self.active && self.note.is_some()).then(|| self.final_period(tables))
+    }
+
+    /// the period with pitch macros, vibrato and fine pitch applied
+    fn final_period(&self, tables: &PeriodTables) -> u16{
+        let vibrato = if self.vibrato.speed > 0{
+            tables.vibrato(self.vibrato.depth, self.vibrato.phase)
+        }else{
+            0
+        };
+        let offset = self.pitch_offset - vibrato + self.fine_pitch;
+        let offset = if self.kind.inverted() { -offset } else { offset };
+        match self.kind{
+            // the noise steps through its 16 periods and wraps around
+            ChannelKind::Noise => ((self.period + offset) & 0x0F) as u16,
+            _ => (self.period + offset).clamp(0, self.kind.max_period()) as u16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::hardware_interface::CPU_CLOCK_NTSC;
+    use crate::tests::{apu_module, player, periods, pulse_period};
+
+    /// an instrument whose arpeggio macro runs `values` with `scheme`, 0
+    /// absolute, 1 fixed and 2 relative
+    fn arp_header(scheme: u8, values: &str) -> String{
+        format!("MACRO       1   0  -1  -1   {} : {}\n\
+            INST2A03   0    -1   0  -1  -1  -1 \"Arp\"", scheme, values)
+    }
+
+    #[test]
+    pub fn arpeggio_schemes(){
+        let play = |scheme, values| periods(&apu_module(&arp_header(scheme, values), 0, &[&["A-4 00 F ..."]]), 0, 4);
+        let notes = |notes: [i32; 4]| notes.map(pulse_period);
+        // absolute offsets from the note, the last one holds
+        assert_eq!(play(0, "0 4 7"), notes([69, 73, 76, 76]));
+        // fixed notes counted from C-0, the played note returns after them
+        assert_eq!(play(1, "60 64"), notes([72, 76, 69, 69]));
+        // relative steps move the note for good
+        assert_eq!(play(2, "1 1"), notes([70, 71, 71, 71]));
+
+        // on the noise column fixed values are noise periods, not notes
+        let noise = periods(&apu_module(&arp_header(1, "3 5"), 3, &[&["9-# 00 F ..."]]), 3, 3);
+        assert_eq!(noise, [Some(3), Some(5), Some(9)]);
+    }
+
+    #[test]
+    pub fn pitch_effects_move_the_period(){
+        let (a4, c5) = (pulse_period(69), pulse_period(72));
+        // 0xy steps through the note, x and y semitones up, a tick each
+        let arp = periods(&apu_module("", 0, &[&["A-4 .. F 047"]]), 0, 4);
+        assert_eq!(arp, [a4, pulse_period(73), pulse_period(76), a4]);
+        // 000 stops it on the note, even when it was last on y
+        let stopped = periods(&apu_module("", 0, &[&["A-4 .. F 047", "... .. . 000"]]), 0, 8);
+        assert_eq!(stopped[5], pulse_period(76));
+        assert_eq!(stopped[6..], [a4, a4]);
+
+        // 3xx glides from the note already playing and stops on the new one
+        let porta = periods(&apu_module("", 0, &[&["A-4 .. F ...", "C-5 .. . 305", "... .. . ...", "... .. . ..."]]), 0, 24);
+        assert_eq!(porta[6], a4.map(|period| period - 5));
+        assert_eq!(porta[17], c5);
+        assert_eq!(porta[23], c5);
+
+        // Qxy and Rxy aim y semitones away at 2x+1 a tick and stop there
+        let up = periods(&apu_module("", 0, &[&["A-4 .. F Q23", "... .. . ..."]]), 0, 12);
+        assert_eq!(up[0], a4.map(|period| period - 5));
+        assert_eq!(up[11], c5);
+        let down = periods(&apu_module("", 0, &[&["A-4 .. F R23", "... .. . ..."]]), 0, 12);
+        assert_eq!(down[0], a4.map(|period| period + 5));
+        assert_eq!(down[11], pulse_period(66));
+    }
+
+    #[test]
+    pub fn delays_and_cuts_wait_their_ticks(){
+        let a4 = pulse_period(69);
+        let delayed = periods(&apu_module("", 0, &[&["A-4 .. F G03", "... .. . ..."]]), 0, 4);
+        assert_eq!(delayed, [None, None, None, a4]);
+        // a row still waiting when the next arrives plays first, here its
+        // note is what the next row's 1xx slides
+        let late = periods(&apu_module("", 0, &[&["A-4 .. F G08", "... .. . 101"]]), 0, 7);
+        assert!(late[..6].iter().all(Option::is_none));
+        assert_eq!(late[6], a4.map(|period| period - 1));
+
+        let cut = periods(&apu_module("", 0, &[&["A-4 .. F S03"]]), 0, 4);
+        assert_eq!(cut, [a4, a4, a4, None]);
+    }
+
+    #[test]
+    pub fn dpcm_retrigger_restarts_the_sample(){
+        let header = format!("DPCMDEF   0    17 \"Kick\"\n\
+            DPCM : {}\n\
+            INST2A03   0    -1  -1  -1  -1  -1 \"Kick\"\n\
+            KEYDPCM   0   3   0     0  15   0     0  -1", vec!["55"; 17].join(" "));
+        // the sample lasts well under a tick, so only restarts keep it playing
+        let playing = |cell: &str| {
+            let (mut player, mut board) = player(&apu_module(&header, 4, &[&[cell]]));
+            let mut ticks = 0;
+            for _ in 0..6{
+                player.tick(&mut board);
+                ticks += (board.apu.read_status() >> 4) & 1;
+                for _ in 0..(CPU_CLOCK_NTSC / 60.0) as u32{
+                    board.clock();
+                }
+            }
+            ticks
+        };
+        assert_eq!(playing("C-3 00 . ..."), 1);
+        // on ticks 0, 2 and 4
+        assert_eq!(playing("C-3 00 . X02"), 3);
+    }
+
+    /// the shortest shift that repeats `values`
+    fn cycle<T: PartialEq>(values: &[T]) -> usize{
+        let half = values.len() / 2;
+        (1..half).find(|&shift| values[..half] == values[shift..shift + half]).unwrap_or(half)
+    }
+
+    #[test]
+    pub fn tremolo_dips_twice_per_vibrato_cycle(){
+        let tables = PeriodTables::default();
+        let mut channel = Channel::new(ChannelKind::Pulse1);
+        channel.period = 0x200;
+        channel.vibrato.set(Some((1, 0x0F)));
+        channel.tremolo.set(Some((1, 0x0F)));
+        let (mut periods, mut volumes) = (vec![], vec![]);
+        for _ in 0..128{
+            periods.push(channel.final_period(&tables));
+            volumes.push(channel.output_volume(&tables));
+            channel.vibrato.step();
+            channel.tremolo.step();
+        }
+        assert_eq!(cycle(&periods), 64);
+        assert_eq!(cycle(&volumes), 32);
+    }
+
+    #[test]
+    pub fn exx_sets_the_volume(){
+        let tables = PeriodTables::default();
+        let mut board = Board::new();
+        let mut channel = Channel::new(ChannelKind::Pulse1);
+        channel.apply_effect(&tables, Effect::Volume(0x05), &mut board);
+        assert_eq!(channel.output_volume(&tables), 5);
+        channel.apply_effect(&tables, Effect::Volume(0x3F), &mut board);
+        assert_eq!(channel.output_volume(&tables), 15);
+    }
+
+    #[test]
+    pub fn noise_pitch_wraps(){
+        let noise = |cell| periods(&apu_module("", 3, &[&[cell]]), 3, 1)[0];
+        assert_eq!(noise("9-# .. F ..."), Some(9));
+        // Pxx and pitch macros move the noise period like any other
+        assert_eq!(noise("9-# .. F P81"), Some(8));
+        assert_eq!(noise("9-# .. F P7E"), Some(11));
+        let pitch = "MACRO       2   0  -1  -1   0 : 3\n\
+            INST2A03   0    -1  -1   0  -1  -1 \"Pitch\"";
+        assert_eq!(periods(&apu_module(pitch, 3, &[&["9-# 00 F ..."]]), 3, 1), [Some(12)]);
+        // and it wraps around the 16 settings
+        assert_eq!(noise("0-# .. F P81"), Some(15));
+        assert_eq!(noise("F-# .. F P7F"), Some(0));
+    }
+}
@@ -0,0 +1,188 @@
+use crate::hardware_interface::Region;
+
+/// FamiTracker's note range, C-0 to B-7
+pub const NOTE_COUNT: usize = 96;
+/// MIDI number of C-0, notes from the tokenizer are MIDI numbers
+pub const NOTE_OFFSET: u32 = 12;
+
+/// peak period offset of each 4xy depth
+const VIBRATO_DEPTH: [f64; 16] = [
+    1.0, 1.5, 2.5, 4.0, 5.0, 7.0, 10.0, 12.0, 14.0, 17.0, 22.0, 30.0, 44.0, 64.0, 96.0, 128.0,
+];
+/// the depths of FamiTracker's old vibrato, which only bends the pitch up
+/// and ramps linearly instead of following a sine
+const OLD_VIBRATO_DEPTH: [i32; 16] = [
+    1, 1, 2, 3, 4, 7, 8, 15, 16, 31, 32, 63, 64, 127, 128, 255,
+];
+
+/// Timer periods for every note the tracker can enter, plus the quarter
+/// wave shared by vibrato and tremolo.
+#[derive(Debug, Clone)]
+pub struct PeriodTables{
+    pulse: [u16; NOTE_COUNT],
+    /// the VRC6 pulses divide like the 2A03's but have 12 bit timers
+    vrc6_pulse: [u16; NOTE_COUNT],
+    sawtooth: [u16; NOTE_COUNT],
+    /// the FDS takes a frequency rather than a period
+    fds: [u16; NOTE_COUNT],
+    /// N163 frequencies in sixteenths, for one channel and a one sample wave
+    n163: [u16; NOTE_COUNT],
+    /// VRC7 F-Num shifted up by the block, a 9 bit F-Num is taken from it
+    /// at whatever block fits
+    vrc7: [u16; NOTE_COUNT],
+    /// each 5B square flips every period ticks of a 16 cycle prescaler
+    s5b: [u16; NOTE_COUNT],
+    vibrato: [i32; 256],
+    /// VIBRATO 1, the vibrato swings both ways around the note
+    new_vibrato: bool,
+}
+
+impl PeriodTables{
+    /// every chip is clocked by the CPU, so its periods follow the region,
+    /// `tuning` is TUNING's semitones and cents, `new_vibrato` is VIBRATO 1
+    pub fn new(region: Region, tuning: (i32, i32), new_vibrato: bool) -> Self{
+        let clock = region.cpu_clock();
+        let detune = tuning.0 as f64 + tuning.1 as f64 / 100.0;
+        let mut pulse = [0; NOTE_COUNT];
+        let mut vrc6_pulse = [0; NOTE_COUNT];
+        let mut sawtooth = [0; NOTE_COUNT];
+        let mut fds = [0; NOTE_COUNT];
+        let mut n163 = [0; NOTE_COUNT];
+        let mut vrc7 = [0; NOTE_COUNT];
+        let mut s5b = [0; NOTE_COUNT];
+        for note in 0..NOTE_COUNT{
+            let midi = note as f64 + NOTE_OFFSET as f64;
+            let freq = 440.0 * 2f64.powf((midi + detune - 69.0) / 12.0);
+            pulse[note] = Self::period(clock / (16.0 * freq), 0x7FF);
+            vrc6_pulse[note] = Self::period(clock / (16.0 * freq), 0xFFF);
+            sawtooth[note] = Self::period(clock / (14.0 * freq), 0xFFF);
+            fds[note] = (freq * (1 << 22) as f64 / clock).round().min(0xFFF as f64) as u16;
+            n163[note] = (freq * (15 << 20) as f64 / clock).round().min(0xFFFF as f64) as u16;
+            vrc7[note] = (freq * (36 << 19) as f64 / clock).round().min(0xFFFF as f64) as u16;
+            s5b[note] = (clock / (32.0 * freq)).round().clamp(1.0, 0xFFF as f64) as u16;
+        }
+        let mut vibrato = [0; 256];
+        for (depth, (peak, old_peak)) in VIBRATO_DEPTH.iter().zip(OLD_VIBRATO_DEPTH).enumerate(){
+            for phase in 0..16{
+                vibrato[depth * 16 + phase] = if new_vibrato{
+                    let angle = phase as f64 / 16.0 * std::f64::consts::FRAC_PI_2;
+                    (angle.sin() * peak) as i32
+                }else{
+                    phase as i32 * old_peak / 16 + 1
+                };
+            }
+        }
+        Self { pulse, vrc6_pulse, sawtooth, fds, n163, vrc7, s5b, vibrato, new_vibrato }
+    }
+
+    fn period(cycles: f64, max: u16) -> u16{
+        (cycles - 1.0).round().clamp(0.0, max as f64) as u16
+    }
+
+    fn index(note: i32) -> usize{
+        (note - NOTE_OFFSET as i32).clamp(0, NOTE_COUNT as i32 - 1) as usize
+    }
+
+    pub fn pulse(&self, note: i32) -> u16{
+        self.pulse[Self::index(note)]
+    }
+
+    /// FamiTracker gives the triangle the pulse periods, its 32 step
+    /// sequence plays each note an octave below the pulses
+    pub fn triangle(&self, note: i32) -> u16{
+        self.pulse(note)
+    }
+
+    pub fn vrc6_pulse(&self, note: i32) -> u16{
+        self.vrc6_pulse[Self::index(note)]
+    }
+
+    pub fn sawtooth(&self, note: i32) -> u16{
+        self.sawtooth[Self::index(note)]
+    }
+
+    pub fn fds(&self, note: i32) -> u16{
+        self.fds[Self::index(note)]
+    }
+
+    pub fn n163(&self, note: i32) -> u16{
+        self.n163[Self::index(note)]
+    }
+
+    pub fn vrc7(&self, note: i32) -> u16{
+        self.vrc7[Self::index(note)]
+    }
+
+    pub fn s5b(&self, note: i32) -> u16{
+        self.s5b[Self::index(note)]
+    }
+
+    /// the lowest block whose F-Num for `note` fits in 9 bits
+    pub fn vrc7_block(&self, note: i32) -> u8{
+        let mut frequency = self.vrc7(note);
+        let mut block = 0;
+        while frequency > 0x1FF && block < 7{
+            frequency >>= 1;
+            block += 1;
+        }
+        block
+    }
+
+    /// the 4xy offset, the old style lifts the sine to sit above the note
+    pub fn vibrato(&self, depth: u8, phase: u8) -> i32{
+        let offset = self.oscillator(depth, phase);
+        if self.new_vibrato{
+            offset
+        }else{
+            (offset + self.vibrato[(depth as usize & 0x0F) * 16 + 15] + 1) >> 1
+        }
+    }
+
+    /// offset of a 64 step sine oscillator with a 0-F depth
+    pub fn oscillator(&self, depth: u8, phase: u8) -> i32{
+        let row = (depth as usize & 0x0F) * 16;
+        let phase = phase as usize & 0x3F;
+        match phase >> 4{
+            0 => self.vibrato[row + phase],
+            1 => self.vibrato[row + 15 - (phase - 16)],
+            2 => -self.vibrato[row + (phase - 32)],
+            _ => -self.vibrato[row + 15 - (phase - 48)],
+        }
+    }
+}
+
+impl Default for PeriodTables{
+    fn default() -> Self {
+        Self::new(Region::default(), (0, 0), true)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::hardware_interface::CPU_CLOCK_NTSC;
+
+    #[test]
+    pub fn triangle_plays_an_octave_below(){
+        let tables = PeriodTables::new(Region::Ntsc, (0, 0), true);
+        // A-4 is 440 Hz on the pulses and 220 Hz on the triangle
+        let a4 = 69;
+        let pulse = CPU_CLOCK_NTSC / (16.0 * (tables.pulse(a4) + 1) as f64);
+        let triangle = CPU_CLOCK_NTSC / (32.0 * (tables.triangle(a4) + 1) as f64);
+        assert!((pulse - 440.0).abs() < 1.0, "{}", pulse);
+        assert!((triangle - 220.0).abs() < 0.5, "{}", triangle);
+    }
+
+    #[test]
+    pub fn vibrato_styles(){
+        // the new style follows a sine up to the depth's peak
+        let new = PeriodTables::new(Region::Ntsc, (0, 0), true);
+        assert_eq!([0, 8, 15].map(|phase| new.oscillator(0x0F, phase)), [0, 90, 127]);
+        assert_eq!(new.oscillator(0x03, 15), 3);
+        // the old style is a straight ramp one above j * depth / 16
+        let old = PeriodTables::new(Region::Ntsc, (0, 0), false);
+        assert_eq!([0, 8, 15].map(|phase| old.oscillator(0x0F, phase)), [1, 128, 240]);
+        assert_eq!([0, 15].map(|phase| old.oscillator(0x00, phase)), [1, 1]);
+        assert_eq!(old.oscillator(0x07, 15), 15);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::sound_file::SongMacro;
+
+/// `SongMacro::m_type` of each 2A03 instrument macro, also the order of the
+/// macro ids in `INST2A03`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroType{
+    Volume = 0,
+    Arpeggio = 1,
+    Pitch = 2,
+    HiPitch = 3,
+    Duty = 4,
+}
+
+impl MacroType{
+    pub const ALL: [MacroType; 5] = [
+        MacroType::Volume,
+        MacroType::Arpeggio,
+        MacroType::Pitch,
+        MacroType::HiPitch,
+        MacroType::Duty,
+    ];
+}
+
+/// How an arpeggio macro's values move the note, from `m_type_specific`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpScheme{
+    /// offset from the played note
+    Absolute,
+    /// a note of its own, the played note returns when the macro ends
+    Fixed,
+    /// offset added to the note every tick, the change sticks
+    Relative,
+}
+
+impl From<u8> for ArpScheme{
+    fn from(setting: u8) -> Self {
+        match setting{
+            1 => ArpScheme::Fixed,
+            2 => ArpScheme::Relative,
+            _ => ArpScheme::Absolute,
+        }
+    }
+}
+
+/// Steps through one instrument macro, once per tick, the way FamiTracker's
+/// sequence handler does.
+#[derive(Debug)]
+pub struct Sequence{
+    vals: Vec<i8>,
+    setting: u8,
+    loop_point: Option<usize>,
+    release_point: Option<usize>,
+    position: usize,
+    running: bool,
+    released: bool,
+}
+
+impl Sequence{
+    pub fn new(song_macro: &SongMacro) -> Self{
+        Self {
+            vals: song_macro.vals.clone(),
+            setting: song_macro.m_type_specific,
+            loop_point: song_macro.m_loop.map(usize::from),
+            release_point: song_macro.m_release.map(usize::from),
+            position: 0,
+            running: !song_macro.vals.is_empty(),
+            released: false,
+        }
+    }
+
+    pub fn arp_scheme(&self) -> ArpScheme{
+        ArpScheme::from(self.setting)
+    }
+
+    /// the value for this tick, `None` once the sequence has ended
+    pub fn step(&mut self) -> Option<i8>{
+        if !self.running{
+            return None;
+        }
+        let value = self.vals[self.position];
+        self.position += 1;
+
+        let at_release = self.release_point.is_some_and(|release| self.position == release + 1);
+        if at_release || self.position >= self.vals.len(){
+            match self.loop_point{
+                // a loop before the release point stops once released
+                Some(loop_point) if !(self.released && self.release_point.is_some()) => {
+                    self.position = loop_point.min(self.vals.len() - 1);
+                }
+                _ => {
+                    if self.position >= self.vals.len(){
+                        self.running = false;
+                    }else if !self.released{
+                        // hold on the release point until the note is released
+                        self.position -= 1;
+                    }
+                }
+            }
+        }
+        Some(value)
+    }
+
+    /// continues from the release point on the next step
+    pub fn release(&mut self){
+        self.released = true;
+        if let Some(release) = self.release_point{
+            if release < self.vals.len(){
+                self.position = release;
+                self.running = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::sound_file::Chip;
+
+    /// the first 10 values of 0 1 2 3 with a loop and release point, -1
+    /// for none, released before the 7th step
+    fn play(loop_point: i8, release: i8) -> Vec<Option<i8>>{
+        let mut sequence = Sequence::new(&SongMacro{
+            chip: Chip::Apu,
+            m_type: MacroType::Arpeggio as u8,
+            m_id: 0,
+            m_loop: u8::try_from(loop_point).ok(),
+            m_release: u8::try_from(release).ok(),
+            m_type_specific: 0,
+            vals: vec![0, 1, 2, 3],
+        });
+        (0..10).map(|step| {
+            if step == 6{
+                sequence.release();
+            }
+            sequence.step()
+        }).collect()
+    }
+
+    fn values(values: &[i8]) -> Vec<Option<i8>>{
+        let mut values: Vec<_> = values.iter().copied().map(Some).collect();
+        values.resize(10, None);
+        values
+    }
+
+    #[test]
+    pub fn loop_only(){
+        assert_eq!(play(1, -1), values(&[0, 1, 2, 3, 1, 2, 3, 1, 2, 3]));
+    }
+
+    #[test]
+    pub fn release_only(){
+        // holds on the release point until released, then plays on and ends
+        assert_eq!(play(-1, 1), values(&[0, 1, 1, 1, 1, 1, 1, 2, 3]));
+    }
+
+    #[test]
+    pub fn loop_before_release(){
+        // loops until released, then plays out from the release point
+        assert_eq!(play(0, 2), values(&[0, 1, 2, 0, 1, 2, 2, 3]));
+    }
+
+    #[test]
+    pub fn loop_after_release(){
+        // reaching the release point jumps to the loop, which runs until
+        // released, then the release point plays out
+        assert_eq!(play(2, 1), values(&[0, 1, 2, 3, 2, 3, 1, 2, 3]));
+    }
+}
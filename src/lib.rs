@@ -1,5 +1,3 @@
-use interpreter::Interpreter;
-
 #[macro_use]
 extern crate bitfield;
 
@@ -12,8 +10,13 @@ pub mod hardware_interface;
 
 
 
+#[cfg(test)]
 pub mod tests{
+    use std::sync::{Arc, Mutex};
+
     use crate::interpreter::Interpreter;
+    use crate::hardware_interface::{Board, Thing, FilterSettings, CPU_CLOCK_NTSC, SAMPLE_RATE};
+    use crate::sound_file::Effect;
 
 
     #[test]
@@ -24,7 +27,7 @@ pub mod tests{
         match crate::parser::read_text(str){
             Ok(info) => {
                 //println!("{:#?}", info);
-                let int = Interpreter::new(&info);
+                let _int = Interpreter::new(Arc::new(info));
                 std::thread::sleep(std::time::Duration::from_millis(10000));
             },
             Err(err) => {
@@ -32,4 +35,498 @@ pub mod tests{
             },
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn band_limited_spectrum(){
+        const LEN: usize = 8192;
+        // a 50% duty pulse around 1.75 kHz, point sampling this folds most of
+        // its harmonics back down into the audible range
+        let period = 0x3F;
+        let mut board = Board::new();
+        board.write_register(0x4015, 0x01);
+        board.write_register(0x4000, 0xBF);
+        board.write_register(0x4002, period);
+        board.write_register(0x4003, 0x08);
+
+        let settings = FilterSettings{
+            high_pass_90: false,
+            high_pass_440: false,
+            low_pass_14k: false,
+        };
+        let samples: Vec<f64> = Thing::new(Arc::new(Mutex::new(board)), settings)
+            .skip(1024)
+            .take(LEN)
+            .map(f64::from)
+            .collect();
+
+        // the reference is an ideal band-limited square wave, which only has
+        // energy at the odd harmonics of its fundamental
+        let fundamental = CPU_CLOCK_NTSC / (16.0 * (period as f64 + 1.0));
+        let bin_width = SAMPLE_RATE as f64 / LEN as f64;
+        let is_harmonic = |freq: f64| {
+            let harmonic = (freq / fundamental).round();
+            harmonic as u32 % 2 == 1 && (freq - harmonic * fundamental).abs() <= 4.0 * bin_width
+        };
+
+        let mean = samples.iter().sum::<f64>() / LEN as f64;
+        let mut harmonic_energy = 0.0;
+        let mut alias_energy = 0.0;
+        for bin in 4..LEN / 2{
+            let (mut re, mut im) = (0.0, 0.0);
+            for (n, sample) in samples.iter().enumerate(){
+                let hann = 0.5 - 0.5 * (2.0 * std::f64::consts::PI * n as f64 / LEN as f64).cos();
+                let angle = 2.0 * std::f64::consts::PI * (bin * n % LEN) as f64 / LEN as f64;
+                re += (sample - mean) * hann * angle.cos();
+                im -= (sample - mean) * hann * angle.sin();
+            }
+            let energy = re * re + im * im;
+            if is_harmonic(bin as f64 * bin_width){
+                harmonic_energy += energy;
+            }else{
+                alias_energy += energy;
+            }
+        }
+        let ratio_db = 10.0 * (alias_energy / harmonic_energy).log10();
+        assert!(ratio_db < -40.0, "aliasing only {:.1} dB below the harmonics", ratio_db);
+    }
+
+    #[test]
+    pub fn zero_speed_is_rejected(){
+        let text = "TRACK   1   0 150 \"Song\"\n\
+            COLUMNS : 1 1 1 1 1\n\n\
+            ORDER 00 : 00 00 00 00 00\n\n\
+            PATTERN 00\n\
+            ROW 00 : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ...\n";
+        assert!(crate::parser::read_text(&text.replace("   0 150", "   1 150")).is_ok());
+        let err = crate::parser::read_text(text).unwrap_err();
+        assert!(matches!(*err.kind, crate::parser::ParseErrorKind::NumberOutOfRange{ .. }), "{:?}", err.kind);
+    }
+
+    #[test]
+    pub fn unmapped_writes_are_dropped(){
+        let mut board = Board::new();
+        board.set_expansion(0x3F);
+        for address in [0x4009, 0x4014, 0x4016, 0x4018, 0x5012, 0x9004, 0xC001, 0xFFFF]{
+            board.write_register(address, 0xFF);
+        }
+        board.clock();
+        assert!(board.output().is_finite());
+    }
+
+    /// a 2A03 module playing `patterns` in order, every row a single cell
+    /// in `column`, `header` goes above the track
+    pub fn apu_module(header: &str, column: usize, patterns: &[&[&str]]) -> String{
+        let empty = "... .. . ...";
+        let mut text = format!("{}\nTRACK {:3}   6 150 \"Song\"\nCOLUMNS : 1 1 1 1 1\n\n", header, patterns[0].len());
+        for frame in 0..patterns.len(){
+            text += &format!("ORDER {:02X} :{}\n", frame, format!(" {:02X}", frame).repeat(5));
+        }
+        for (id, rows) in patterns.iter().enumerate(){
+            text += &format!("\nPATTERN {:02X}\n", id);
+            for (row, cell) in rows.iter().enumerate(){
+                let cells: Vec<&str> = (0..5).map(|other| if other == column { *cell } else { empty }).collect();
+                text += &format!("ROW {:02X} : {}\n", row, cells.join(" : "));
+            }
+        }
+        text
+    }
+
+    pub fn player(text: &str) -> (crate::interpreter::Player, Board){
+        let file = crate::parser::read_text(text).unwrap();
+        (crate::interpreter::Player::new(Arc::new(file)), Board::new())
+    }
+
+    /// `column`'s period after each of `ticks` ticks
+    pub fn periods(text: &str, column: usize, ticks: usize) -> Vec<Option<u16>>{
+        let (mut player, mut board) = player(text);
+        (0..ticks).map(|_| {
+            player.tick(&mut board);
+            player.period(column)
+        }).collect()
+    }
+
+    /// the pulse period FamiTracker gives a MIDI note
+    pub fn pulse_period(midi: i32) -> Option<u16>{
+        let freq = 440.0 * 2f64.powf((midi - 69) as f64 / 12.0);
+        Some((CPU_CLOCK_NTSC / (16.0 * freq) - 1.0).round() as u16)
+    }
+
+    /// every effect written in res/ must parse and print back unchanged,
+    /// tetris_gb.txt has a corrupted pattern so only its valid cells count
+    #[test]
+    pub fn effect_letters_round_trip(){
+        let mut checked = 0;
+        for entry in std::fs::read_dir("res").unwrap(){
+            let path = entry.unwrap().path();
+            let text = std::fs::read_to_string(&path).unwrap();
+            let corrupted = path.ends_with("tetris_gb.txt");
+            for line in text.lines().filter(|line| line.starts_with("ROW")){
+                for column in line.split(" : ").skip(1){
+                    for cell in column.split_whitespace().skip(3).filter(|cell| *cell != "..."){
+                        let effect = match Effect::try_from(cell){
+                            Ok(effect) => effect,
+                            Err(_) if corrupted => continue,
+                            Err(err) => panic!("{} in {}: {}", cell, path.display(), err),
+                        };
+                        let printed = effect.to_string();
+                        assert_eq!(Effect::try_from(printed.as_str()).unwrap(), effect);
+                        // Cxx ignores its parameter
+                        if effect != Effect::Halt{
+                            assert_eq!(printed, cell, "{}", path.display());
+                        }
+                        checked += 1;
+                    }
+                }
+            }
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    pub fn four_effect_columns(){
+        let text = "TRACK   4   6 150 \"Song\"\n\
+            COLUMNS : 4 1 1 1 1\n\n\
+            ORDER 00 : 00 00 00 00 00\n\n\
+            PATTERN 00\n\
+            ROW 00 : C-4 00 F 100 ... 300 4A3 : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ...\n\
+            ROW 01 : ... .. . ... ... ... ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ...\n\
+            ROW 02 : ... .. . ... ... ... ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ...\n\
+            ROW 03 : ... .. . ... ... ... ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ...\n";
+        let file = crate::parser::read_text(text).unwrap();
+        let efx = &file.tracks[0].patterns[0].rows[0].sheet_notes[0].efx;
+        assert_eq!(efx.len(), 4);
+        assert_eq!(efx[3], Some(Effect::VibratoEffect(Some((0xA, 0x3)))));
+        assert_eq!(file.tracks[0].patterns[0].rows[0].sheet_notes[1].efx.len(), 1);
+
+        assert!(crate::parser::read_text(&text.replace("COLUMNS : 4", "COLUMNS : 5")).is_err());
+    }
+
+    #[test]
+    pub fn parse_error_points_at_token(){
+        let text = std::fs::read_to_string("res/tetris_gb.txt").unwrap();
+        let err = crate::parser::read_text(&text).unwrap_err();
+        assert_eq!(*err.kind, crate::parser::ParseErrorKind::UnrecognizedChar('&'));
+        assert_eq!((err.span.start.line(), err.span.start.column()), (1565, 59));
+        let caret = err.to_string().lines().last().unwrap().to_string();
+        assert_eq!(caret.find('^'), Some("1565 | ".len() + 58));
+    }
+
+    #[test]
+    pub fn vrc6_module_loads(){
+        let text = "EXPANSION       1\n\
+            MACROVRC6   4   0  -1  -1   0 : 0 3 7\n\
+            INSTVRC6   0    -1  -1  -1  -1   0 \"Saw\"\n\
+            TRACK   1   6 150 \"Song\"\n\
+            COLUMNS : 1 1 1 1 1 1 1 1\n\n\
+            ORDER 00 : 00 00 00 00 00 00 00 00\n\n\
+            PATTERN 00\n\
+            ROW 00 : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : C-4 00 F V07 : ... .. . ... : A-2 00 . ...\n";
+        let file = crate::parser::read_text(text).unwrap();
+        assert_eq!(file.column_chips().len(), 8);
+        match file.instrument(0){
+            Some(crate::sound_file::Instrument::Vrc6(inst)) => assert_eq!(inst.duity_macro, Some(0)),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(file.macros[0].chip, crate::sound_file::Chip::Vrc6);
+        assert_eq!(file.tracks[0].patterns[0].rows[0].sheet_notes[5].efx[0], Some(Effect::AquareDuityNoiseN163Mode(7)));
+
+        assert!(crate::parser::read_text(&text.replace("EXPANSION       1", "EXPANSION       0")).is_err());
+    }
+
+    #[test]
+    pub fn vrc6_channels_play(){
+        let text = "EXPANSION       1\n\
+            INSTVRC6   0    -1  -1  -1  -1  -1 \"Lead\"\n\
+            TRACK   1   6 150 \"Song\"\n\
+            COLUMNS : 1 1 1 1 1 1 1 1\n\n\
+            ORDER 00 : 00 00 00 00 00 00 00 00\n\n\
+            PATTERN 00\n\
+            ROW 00 : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : C-4 00 F ... : ... .. . ... : A-2 00 F ...\n";
+        let file = crate::parser::read_text(text).unwrap();
+        let mut player = crate::interpreter::Player::new(Arc::new(file));
+        let mut board = Board::new();
+        let mut levels = std::collections::BTreeSet::new();
+        for _ in 0..10{
+            player.tick(&mut board);
+            for _ in 0..(CPU_CLOCK_NTSC / 60.0) as u32{
+                board.clock();
+                levels.insert(board.vrc6.as_ref().unwrap().output());
+            }
+        }
+        // a pulse at full volume plus a sawtooth climbing through its steps
+        assert!(levels.contains(&15));
+        assert!(levels.len() > 4);
+    }
+
+    #[test]
+    pub fn mmc5_channels_play(){
+        let text = std::fs::read_to_string("res/Vampire Killer mmc5 remastered.txt").unwrap();
+        let file = crate::parser::read_text(&text).unwrap();
+        let mut player = crate::interpreter::Player::new(Arc::new(file));
+        let mut board = Board::new();
+        let mut sounded = false;
+        for _ in 0..120{
+            player.tick(&mut board);
+            for _ in 0..(CPU_CLOCK_NTSC / 60.0) as u32{
+                board.clock();
+                sounded |= board.mmc5.as_ref().unwrap().output() > 0.0;
+            }
+        }
+        assert!(sounded);
+    }
+
+    /// plays `text` for half a second, counting the times `level` rises
+    /// past `high` after having fallen below `low`
+    fn rising_edges(text: &str, level: impl Fn(&Board) -> f32, low: f32, high: f32) -> usize{
+        let file = crate::parser::read_text(text).unwrap();
+        let mut player = crate::interpreter::Player::new(Arc::new(file));
+        let mut board = Board::new();
+        let mut edges = 0;
+        let mut armed = true;
+        let rate = player.engine_rate();
+        for _ in 0..(rate / 2.0) as u32{
+            player.tick(&mut board);
+            for _ in 0..(board.region().cpu_clock() / rate) as u32{
+                board.clock();
+                let level = level(&board);
+                if armed && level > high{
+                    edges += 1;
+                    armed = false;
+                }else if level < low{
+                    armed = true;
+                }
+            }
+        }
+        edges
+    }
+
+    /// A-4 on the first pulse for half a second and the row reached after
+    /// one second of ticks, `header` goes above the track and `effect` in
+    /// the note's effect column
+    fn pulse_timing(header: &str, effect: &str) -> (usize, usize){
+        let empty: String = (1..16)
+            .map(|row| format!("ROW {:02X} :{}\n", row, " : ... .. . ...".repeat(5).trim_start_matches(" :")))
+            .collect();
+        let text = format!("{}\n\
+            TRACK  16   6 150 \"Song\"\n\
+            COLUMNS : 1 1 1 1 1\n\n\
+            ORDER 00 : 00 00 00 00 00\n\n\
+            PATTERN 00\n\
+            ROW 00 : A-4 .. F {} : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ...\n{}", header, effect, empty);
+        // the idle triangle holds its first step, so the pulse rides on it
+        let edges = rising_edges(&text, |board| board.apu.output(), 0.3, 0.38);
+        let mut player = crate::interpreter::Player::new(Arc::new(crate::parser::read_text(&text).unwrap()));
+        let mut board = Board::new();
+        for _ in 0..player.engine_rate() as u32{
+            player.tick(&mut board);
+        }
+        (edges, player.row())
+    }
+
+    #[test]
+    pub fn machines_keep_pitch_and_speed(){
+        // NTSC, PAL and Dendy, each ticking at 60 or 50 Hz with its own clock
+        for machine in 0..3{
+            let (edges, row) = pulse_timing(&format!("MACHINE {}", machine), "...");
+            assert!((215..=225).contains(&edges), "{} {}", machine, edges);
+            assert_eq!(row, 10, "{}", machine);
+        }
+        assert_eq!(crate::interpreter::Player::new(Arc::new(crate::sound_file::SoundFile{ machine: 1, ..Default::default() })).engine_rate(), 50.0);
+    }
+
+    #[test]
+    pub fn tuning_and_split_apply(){
+        let (edges, _) = pulse_timing("TUNING 12 0", "...");
+        assert!((435..=445).contains(&edges), "{}", edges);
+        let (edges, _) = pulse_timing("TUNING -1 -50", "...");
+        assert!((197..=203).contains(&edges), "{}", edges);
+        // F06 is speed 6 below the default split and tempo 6 above SPLIT 5
+        assert_eq!(pulse_timing("", "F06").1, 10);
+        assert_eq!(pulse_timing("SPLIT 5", "F06").1, 1);
+    }
+
+    /// an FDS square playing A-4, `effect` goes in the FDS column
+    fn fds_edges(effect: &str) -> usize{
+        let wave = [vec!["63"; 32], vec!["0"; 32]].concat().join(" ");
+        let modulation = vec!["1"; 32].join(" ");
+        let text = format!("EXPANSION       4\n\
+            INSTFDS   0     1 256   0   0 \"Square\"\n\
+            FDSWAVE   0 : {}\n\
+            FDSMOD    0 : {}\n\
+            TRACK   1   6 150 \"Song\"\n\
+            COLUMNS : 1 1 1 1 1 1\n\n\
+            ORDER 00 : 00 00 00 00 00 00\n\n\
+            PATTERN 00\n\
+            ROW 00 : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : A-4 00 F {}\n", wave, modulation, effect);
+        rising_edges(&text, |board| board.fds.as_ref().unwrap().output(), 0.4, 0.6)
+    }
+
+    #[test]
+    pub fn fds_channel_plays(){
+        let plain = fds_edges("...");
+        assert!((215..=225).contains(&plain), "{}", plain);
+        // the instrument's modulation table bends the pitch once Hxx gives it depth
+        assert_ne!(fds_edges("H3F"), plain);
+    }
+
+    #[test]
+    pub fn n163_channel_plays(){
+        let square = [vec!["15"; 16], vec!["0"; 16]].concat().join(" ");
+        let flat = vec!["15"; 32].join(" ");
+        let text = format!("EXPANSION       16\n\
+            N163CHANNELS    1\n\
+            INSTN163   0    -1  -1  -1  -1  -1   32    0    2 \"Square\"\n\
+            N163WAVE   0     0 : {}\n\
+            N163WAVE   0     1 : {}\n\
+            TRACK   1   6 150 \"Song\"\n\
+            COLUMNS : 1 1 1 1 1 1\n\n\
+            ORDER 00 : 00 00 00 00 00 00\n\n\
+            PATTERN 00\n\
+            ROW 00 : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : A-4 00 F V00\n", square, flat);
+        let level = |board: &Board| board.n163.as_ref().unwrap().output();
+        let edges = rising_edges(&text, level, -40.0, 40.0);
+        assert!((215..=225).contains(&edges), "{}", edges);
+        // Vxx picks the second wave, which never falls
+        assert!(rising_edges(&text.replace("V00", "V01"), level, -40.0, 40.0) <= 1);
+    }
+
+    /// A-4 on the first 5B square, `effects` fill its three effect columns
+    fn s5b_edges(effects: &str) -> usize{
+        let text = format!("EXPANSION       32\n\
+            TRACK   1   6 150 \"Song\"\n\
+            COLUMNS : 1 1 1 1 1 3 1 1\n\n\
+            ORDER 00 : 00 00 00 00 00 00 00 00\n\n\
+            PATTERN 00\n\
+            ROW 00 : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : A-4 .. F {} : ... .. . ... : ... .. . ...\n", effects);
+        rising_edges(&text, |board| board.s5b.as_ref().unwrap().output(), 0.2, 0.8)
+    }
+
+    #[test]
+    pub fn s5b_channel_plays(){
+        let edges = s5b_edges("... ... ...");
+        assert!((215..=225).contains(&edges), "{}", edges);
+        // a square switched off holds its output high
+        assert!(s5b_edges("V40 ... ...") <= 1);
+        // with the square off the envelope's falling sawtooth is heard,
+        // a 32 step ramp of 256 * $20 cycles is close to A-3
+        let edges = s5b_edges("V60 H20 J08");
+        assert!((105..=112).contains(&edges), "{}", edges);
+    }
+
+    /// a custom VRC7 patch that is close to a plain sine, `second` is the
+    /// note cell of the next row
+    fn vrc7_sine(second: &str) -> String{
+        let quiet = " : ... .. . ...".repeat(5);
+        format!("EXPANSION       2\n\
+            INSTVRC7   0     0 21 21 3F 00 F0 F0 0F 0F \"Sine\"\n\
+            TRACK   2   6 150 \"Song\"\n\
+            COLUMNS :{}\n\n\
+            ORDER 00 :{}\n\n\
+            PATTERN 00\n\
+            ROW 00 : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : A-4 00 F ...{}\n\
+            ROW 01 : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : {} .. . ...{}\n",
+            " 1".repeat(11), " 00".repeat(11), quiet, second, quiet)
+    }
+
+    #[test]
+    pub fn vrc7_channel_plays(){
+        let level = |board: &Board| board.vrc7.as_ref().unwrap().output();
+        let edges = rising_edges(&vrc7_sine("..."), level, -0.5, 0.5);
+        assert!((215..=225).contains(&edges), "{}", edges);
+
+        // FamiTracker keys A-4 as block 4, F-Num 290, which the chip plays
+        // at 440 Hz
+        let mut board = Board::new();
+        board.set_expansion(2);
+        for (register, value) in [0x21, 0x21, 0x3F, 0x00, 0xF0, 0xF0, 0x0F, 0x0F].iter().enumerate(){
+            board.write_register(0x9010, register as u8);
+            board.write_register(0x9030, *value);
+        }
+        for (register, value) in [(0x30, 0x00), (0x10, 290 & 0xFF), (0x20, 0x10 | (4 << 1) | (290 >> 8))]{
+            board.write_register(0x9010, register);
+            board.write_register(0x9030, value as u8);
+        }
+        let mut edges = 0;
+        let mut armed = true;
+        for _ in 0..(CPU_CLOCK_NTSC / 2.0) as u32{
+            board.clock();
+            let level = level(&board);
+            if armed && level > 0.5{
+                edges += 1;
+                armed = false;
+            }else if level < -0.5{
+                armed = true;
+            }
+        }
+        assert!((215..=225).contains(&edges), "{}", edges);
+
+        // a release keys off with the sustain bit, so the note fades slowly,
+        // a cut falls at the patch's fast release rate
+        let tail = |second: &str| {
+            let file = crate::parser::read_text(&vrc7_sine(second)).unwrap();
+            let mut player = crate::interpreter::Player::new(Arc::new(file));
+            let mut board = Board::new();
+            let mut peak: f32 = 0.0;
+            for tick in 0..11{
+                player.tick(&mut board);
+                for _ in 0..(CPU_CLOCK_NTSC / 60.0) as u32{
+                    board.clock();
+                    if tick == 10{
+                        peak = peak.max(level(&board).abs());
+                    }
+                }
+            }
+            peak
+        };
+        assert!(tail("===") > 0.5);
+        assert!(tail("---") < 0.01);
+    }
+
+    #[test]
+    pub fn expansion_instruments_load(){
+        use crate::sound_file::{Chip, Instrument};
+
+        let fds_wave = (0..64).map(|i| (i % 64).to_string()).collect::<Vec<_>>().join(" ");
+        let fds_mod = (0..32).map(|i| (i % 8).to_string()).collect::<Vec<_>>().join(" ");
+        let row = vec![" : ... .. . ..."; 22].concat();
+        let text = format!("EXPANSION       63\n\
+            N163CHANNELS    2\n\
+            MACRON163   4   0  -1  -1   0 : 0 1\n\
+            MACROS5B    0   0  -1  -1   0 : 15 10 5\n\
+            INSTVRC7   0     0 21 21 1C 07 F0 F0 00 0F \"Custom\"\n\
+            INSTFDS   1     1   4   8   0 \"FDS\"\n\
+            FDSWAVE   1 : {}\n\
+            FDSMOD    1 : {}\n\
+            FDSMACRO   1     0  -1  -1   0 : 32 16 0\n\
+            INSTN163   2    -1  -1  -1  -1   0    4    0    2 \"N163\"\n\
+            N163WAVE   2     0 : 0 15 0 15\n\
+            N163WAVE   2     1 : 15 0 15 0\n\
+            INSTS5B   3     0  -1  -1  -1  -1 \"5B\"\n\
+            TRACK   1   6 150 \"Song\"\n\
+            COLUMNS :{}\n\
+            ORDER 00 :{}\n\
+            PATTERN 00\n\
+            ROW 00{}\n", fds_wave, fds_mod, " 1".repeat(22), " 00".repeat(22), row);
+        let file = crate::parser::read_text(&text).unwrap();
+        assert_eq!(file.column_chips().iter().filter(|chip| **chip == Chip::N163).count(), 2);
+        let chips: Vec<Chip> = file.instruments.iter().map(Instrument::chip).collect();
+        assert_eq!(chips, [Chip::Vrc7, Chip::Fds, Chip::N163, Chip::S5B]);
+        match file.instrument(0){
+            Some(Instrument::Vrc7(inst)) => assert_eq!(inst.custom, [0x21, 0x21, 0x1C, 0x07, 0xF0, 0xF0, 0x00, 0x0F]),
+            other => panic!("{:?}", other),
+        }
+        match file.instrument(1){
+            Some(Instrument::Fds(inst)) => {
+                assert_eq!((inst.wave.len(), inst.modulation.len(), inst.macros.len()), (64, 32, 1));
+                assert_eq!(inst.mod_speed, 4);
+            }
+            other => panic!("{:?}", other),
+        }
+        match file.instrument(2){
+            Some(Instrument::N163(inst)) => assert_eq!(inst.waves, [[0, 15, 0, 15], [15, 0, 15, 0]]),
+            other => panic!("{:?}", other),
+        }
+
+        assert!(crate::parser::read_text(&text.replace("FDSWAVE   1", "FDSWAVE   3")).is_err());
+    }
+}
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use rustc_fami::{parser, interpreter::Interpreter};
 
 pub fn main(){
@@ -7,12 +9,12 @@ pub fn main(){
         match parser::read_text(str){
             Ok(info) => {
                 //println!("{:#?}", info);
-                let int = Interpreter::new(&info);
+                let _int = Interpreter::new(Arc::new(info));
                 std::thread::sleep(std::time::Duration::from_millis(10000));
                 println!("asdasdasdasd");
             },
             Err(err) => {
-                println!("{:#?}", err);
+                println!("{}", err);
             },
         }
 }
\ No newline at end of file
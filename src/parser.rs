@@ -1,438 +1,603 @@
-use std::{error::Error};
+use std::{error::Error, fmt::{self, Display}, iter::Peekable};
 
-use crate::{tokenizer::{Tokenizer, self, Token, SkipNLPeekable}, sound_file::*};
+use crate::{tokenizer::{Tokenizer, Token, Span, SkipNLPeekable}, sound_file::*};
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind{
+    UnexpectedToken{ expected: &'static str, found: String },
+    UnexpectedEnd{ expected: &'static str },
+    UnrecognizedChar(char),
+    InvalidNumber(String),
+    NumberOutOfRange{ number: String, range: String },
+    LengthMismatch{ what: &'static str, expected: usize, found: usize },
+    UnknownCommand(String),
+    /// a wave or macro line naming an instrument of another chip or none
+    UndefinedInstrument(i64),
+    InvalidEffect{ effect: String, reason: String },
+}
 
-fn option_note(token: Option<Token>) -> Result<Option<Note>, Box<dyn Error>>{
-    match token{
-        Some(val) => {
-            match val{
-                Token::Note(str) => {
-                    return Ok(Some(str));
-                }
-                Token::DotDotDot => {
-                    return Ok(None)
-                }
-                _ => {
-                    return Err("Note or ... String found other".into());
-                }
+impl Display for ParseErrorKind{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            ParseErrorKind::UnexpectedToken{ expected, found } => write!(f, "expected {}, found {}", expected, found),
+            ParseErrorKind::UnexpectedEnd{ expected } => write!(f, "expected {}, found the end of the file", expected),
+            ParseErrorKind::UnrecognizedChar(char) => write!(f, "unrecognized character {:?}", char),
+            ParseErrorKind::InvalidNumber(number) => write!(f, "{:?} is not a number", number),
+            ParseErrorKind::NumberOutOfRange{ number, range } => write!(f, "{} is out of range, expected {}", number, range),
+            ParseErrorKind::LengthMismatch{ what, expected, found } => {
+                write!(f, "{} expects {} entries, found {}", what, expected, found)
             }
-        },
-        None => Result::Err("Expected Some got None".into()),
+            ParseErrorKind::UnknownCommand(command) => write!(f, "unknown command {}", command),
+            ParseErrorKind::UndefinedInstrument(id) => write!(f, "no instrument {} of this chip", id),
+            ParseErrorKind::InvalidEffect{ effect, reason } => write!(f, "invalid effect {}: {}", effect, reason),
+        }
     }
 }
 
-fn option_instrament(token: Option<Token>) -> Result<Option<u8>, Box<dyn Error>>{
-    match token{
-        Some(val) => {
-            match val{
-                Token::IdentNum(num) => {
-                    return Ok(Some(num.hex()?.try_into()?));
-                }
-                Token::DotDot => {
-                    return Ok(None)
-                }
-                _ => {
-                    return Err("Expected Hex or .. found other".into());
-                }
-            }
-        },
-        None => Result::Err("Expected Some got None".into()),
-    }
+/// A parse failure and the text it points at.
+#[derive(Debug, Clone)]
+pub struct ParseError{
+    pub kind: Box<ParseErrorKind>,
+    pub span: Span,
+    /// the source line holding `span.start`, for the caret display
+    line: String,
 }
 
-fn option_volume(token: Option<Token>) -> Result<Option<u8>, Box<dyn Error>>{
-    match token{
-        Some(val) => {
-            match val{
-                Token::IdentNum(num) => {
-                    let num = num.hex()?;
-                    if num > 15{
-                        return Err("Hex number too big".into())
-                    }
-                    return Ok(Some(num.try_into()?));
-                }
-                Token::Dot => {
-                    return Ok(None)
-                }
-                _ => {
-                    return Err("Expected Hex or .. found other".into());
-                }
-            }
-        },
-        None => Result::Err("Expected Some got None".into()),
+impl ParseError{
+    fn new(kind: ParseErrorKind, span: Span, source: &str) -> Self{
+        let start = span.start.offset().min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find(['\r', '\n']).map_or(source.len(), |i| start + i);
+        Self {
+            kind: Box::new(kind),
+            span,
+            line: source[line_start..line_end].into(),
+        }
     }
 }
 
-fn option_effect(token: Option<Token>) -> Result<Option<Effect>, Box<dyn Error>>{
-    match token{
-        Some(val) => {
-            match val{
-                Token::IdentNum(num) => {
-                    return Ok(Some(num.effect()?));
-                }
-                Token::DotDotDot => {
-                    return Ok(None)
-                }
-                _ => {
-                    return Err("Expected Effect or ... found other".into());
-                }
-            }
-        },
-        None => Result::Err("Expected Some got None".into()),
+/// renders like
+/// ```text
+/// 12:9: expected a note or ..., found X-3
+///    |
+/// 12 | ROW 00 : X-3 00 . ...
+///    |          ^^^
+/// ```
+impl Display for ParseError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (start, end) = (self.span.start, self.span.end);
+        let number = start.line().to_string();
+        let gutter = " ".repeat(number.len());
+        let width = if end.line() == start.line() && end.column() > start.column(){
+            end.column() - start.column()
+        }else{
+            1
+        };
+        writeln!(f, "{}:{}: {}", start.line(), start.column(), self.kind)?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", number, self.line)?;
+        write!(f, "{} | {}{}", gutter, " ".repeat(start.column() - 1), "^".repeat(width))
     }
 }
 
-fn expect_str(token: Option<Token>) -> Result<String, Box<dyn Error>>{
-    match token{
-        Some(val) => {
-            match val{
-                Token::String(str) => {
-                    return Ok(str);
-                }
-                _ => {
-                    return Err("Expected String found other".into());
-                }
-            }
-        },
-        None => Result::Err("Expected Some got None".into()),
-    }
+impl Error for ParseError{}
+
+pub fn read_text(str: &str) -> Result<SoundFile, ParseError>{
+    Parser::new(str).read()
 }
 
-fn expect_nl(token: Option<Token>) -> Result<(), Box<dyn Error>>{
-    match token{
-        Some(val) => {
-            match val{
-                Token::NewLine => {
-                    return Ok(());
-                }
-                _ => {
-                    return Err("Expected NewLine found other".into());
-                }
-            }
-        },
-        None => Result::Err("Expected Some got None".into()),
-    }
+struct Parser<'a>{
+    source: &'a str,
+    tokens: Peekable<Tokenizer<'a>>,
+    /// span of the last token taken, errors about missing tokens point here
+    last: Span,
 }
 
-fn expect_colon(token: Option<Token>) -> Result<(), Box<dyn Error>>{
-    match token{
-        Some(val) => {
-            match val{
-                Token::Colon => {
-                    return Ok(());
-                }
-                _ => {
-                    return Err("Expected Colon found other".into());
-                }
+impl<'a> Parser<'a>{
+    fn new(source: &'a str) -> Self{
+        Self {
+            source,
+            tokens: Tokenizer::new(source).peekable(),
+            last: Default::default(),
+        }
+    }
+
+    fn error(&self, kind: ParseErrorKind, span: Span) -> ParseError{
+        ParseError::new(kind, span, self.source)
+    }
+
+    fn next(&mut self, expected: &'static str) -> Result<(Token, Span), ParseError>{
+        match self.tokens.next(){
+            Some((Token::Error(char, _), span)) => Err(self.error(ParseErrorKind::UnrecognizedChar(char), span)),
+            Some((token, span)) => {
+                self.last = span;
+                Ok((token, span))
             }
-        },
-        None => Result::Err("Expected Some got None".into()),
+            None => Err(self.error(ParseErrorKind::UnexpectedEnd{ expected }, self.last)),
+        }
     }
-}
 
-fn expect_dec_num(token: Option<Token>) -> Result<i32, Box<dyn Error>>{
-    match token{
-        Some(val) => {
-            match val{
-                Token::IdentNum(num) => {
-                    return Ok(num.dec()?);
-                }
-                _ => {
-                    return Err("Expected NewLine found other".into());
+    fn peek(&mut self) -> Option<&Token>{
+        self.tokens.peek().map(|(token, _)| token)
+    }
+
+    fn peek_skipping_nl(&mut self) -> Option<&Token>{
+        self.tokens.peek_skipping_nl()
+    }
+
+    /// true and consumes the next ident if it is `ident`, after skipping
+    /// blank lines
+    fn next_is(&mut self, ident: &str) -> bool{
+        match self.peek_skipping_nl(){
+            Some(Token::IdentNum(val)) if val == ident => {
+                if let Some((_, span)) = self.tokens.next(){
+                    self.last = span;
                 }
+                true
             }
-        },
-        None => Result::Err("Expected Some got None".into()),
+            _ => false,
+        }
     }
-}
-fn expect_opt_dec_num(token: Option<Token>) -> Result<Option<u8>, Box<dyn Error>>{
-    match token{
-        Some(val) => {
-            match val{
-                Token::IdentNum(num) => {
-                    let num = num.dec()?;
-                    if num < 0{
-                        return Ok(None);
-                    }else{
-                        return Ok(Option::Some(num.try_into()?));
-                    }
-                }
-                _ => {
-                    return Err("Expected NewLine found other".into());
-                }
+
+    fn unexpected(&self, expected: &'static str, token: &Token, span: Span) -> ParseError{
+        let found = match token{
+            Token::NewLine => "the end of the line".into(),
+            _ => span.slice(self.source).into(),
+        };
+        self.error(ParseErrorKind::UnexpectedToken{ expected, found }, span)
+    }
+
+    /// fits a parsed number into the field it is read into
+    fn fit<T: TryFrom<i64>>(&self, number: i64, span: Span) -> Result<T, ParseError>{
+        T::try_from(number).map_err(|_| {
+            let range = format!("a {}", std::any::type_name::<T>());
+            self.error(ParseErrorKind::NumberOutOfRange{ number: span.slice(self.source).into(), range }, span)
+        })
+    }
+
+    fn out_of_range(&self, range: &str, span: Span) -> ParseError{
+        self.error(ParseErrorKind::NumberOutOfRange{ number: span.slice(self.source).into(), range: range.into() }, span)
+    }
+
+    fn expect_str(&mut self) -> Result<String, ParseError>{
+        match self.next("a string")?{
+            (Token::String(str), _) => Ok(str),
+            (token, span) => Err(self.unexpected("a string", &token, span)),
+        }
+    }
+
+    fn expect_nl(&mut self) -> Result<(), ParseError>{
+        match self.tokens.next(){
+            // the last line may end without a newline
+            None | Some((Token::NewLine, _)) => Ok(()),
+            Some((token, span)) => Err(self.unexpected("the end of the line", &token, span)),
+        }
+    }
+
+    fn expect_colon(&mut self) -> Result<(), ParseError>{
+        match self.next(":")?{
+            (Token::Colon, _) => Ok(()),
+            (token, span) => Err(self.unexpected(":", &token, span)),
+        }
+    }
+
+    fn expect_dec_span(&mut self) -> Result<(i64, Span), ParseError>{
+        match self.next("a decimal number")?{
+            (Token::IdentNum(num), span) => match num.dec(){
+                Ok(num) => Ok((num as i64, span)),
+                Err(_) => Err(self.error(ParseErrorKind::InvalidNumber(num.as_str().into()), span)),
+            },
+            (token, span) => Err(self.unexpected("a decimal number", &token, span)),
+        }
+    }
+
+    fn expect_dec<T: TryFrom<i64>>(&mut self) -> Result<T, ParseError>{
+        let (num, span) = self.expect_dec_span()?;
+        self.fit(num, span)
+    }
+
+    /// a decimal number where -1 means none
+    fn expect_opt_dec<T: TryFrom<i64>>(&mut self) -> Result<Option<T>, ParseError>{
+        let (num, span) = self.expect_dec_span()?;
+        if num < 0{
+            Ok(None)
+        }else{
+            self.fit(num, span).map(Some)
+        }
+    }
+
+    fn hex(&self, token: Token, span: Span, expected: &'static str) -> Result<(i64, Span), ParseError>{
+        match token{
+            Token::IdentNum(num) => match num.hex(){
+                Ok(num) => Ok((num as i64, span)),
+                Err(_) => Err(self.error(ParseErrorKind::InvalidNumber(num.as_str().into()), span)),
+            },
+            token => Err(self.unexpected(expected, &token, span)),
+        }
+    }
+
+    fn expect_hex<T: TryFrom<i64>>(&mut self) -> Result<T, ParseError>{
+        let (token, span) = self.next("a hex number")?;
+        let (num, span) = self.hex(token, span, "a hex number")?;
+        self.fit(num, span)
+    }
+
+    /// reads hex numbers until the end of the line
+    fn hex_list<T: TryFrom<i64>>(&mut self) -> Result<Vec<T>, ParseError>{
+        let mut list = Vec::new();
+        while let Some(Token::IdentNum(_)) = self.peek(){
+            list.push(self.expect_hex()?);
+        }
+        Ok(list)
+    }
+
+    fn option_note(&mut self) -> Result<Option<Note>, ParseError>{
+        match self.next("a note or ...")?{
+            (Token::Note(note), _) => Ok(Some(note)),
+            (Token::DotDotDot, _) => Ok(None),
+            (token, span) => Err(self.unexpected("a note or ...", &token, span)),
+        }
+    }
+
+    fn option_instrument(&mut self) -> Result<Option<u8>, ParseError>{
+        match self.next("an instrument or ..")?{
+            (Token::DotDot, _) => Ok(None),
+            (token, span) => {
+                let (num, span) = self.hex(token, span, "an instrument or ..")?;
+                self.fit(num, span).map(Some)
             }
-        },
-        None => Result::Err("Expected Some got None".into()),
+        }
     }
-}
 
-fn expect_hex_num(token: Option<Token>) -> Result<u32, Box<dyn Error>>{
-    match token{
-        Some(val) => {
-            match val{
-                Token::IdentNum(num) => {
-                    return Ok(num.hex()?);
-                }
-                _ => {
-                    return Err("Expected NewLine found other".into());
+    fn option_volume(&mut self) -> Result<Option<u8>, ParseError>{
+        match self.next("a volume or .")?{
+            (Token::Dot, _) => Ok(None),
+            (token, span) => {
+                let (num, span) = self.hex(token, span, "a volume or .")?;
+                if num > 0x0F{
+                    return Err(self.out_of_range("0 to F", span));
                 }
+                Ok(Some(num as u8))
             }
-        },
-        None => Result::Err("Expected Some got None".into()),
+        }
     }
-}
 
-pub fn read_text(str: &str) -> Result<SoundFile, Box<dyn Error>>{
-   
-    let mut file = SoundFile::default();
-    let mut tokenizer = Tokenizer::from_str(str).peekable();
-    while let Option::Some(token) = tokenizer.next(){
+    fn option_effect(&mut self, chip: Chip) -> Result<Option<Effect>, ParseError>{
+        match self.next("an effect or ...")?{
+            (Token::IdentNum(num), span) => match num.effect(chip){
+                Ok(effect) => Ok(Some(effect)),
+                Err(err) => Err(self.error(ParseErrorKind::InvalidEffect{ effect: num.as_str().into(), reason: err.to_string() }, span)),
+            },
+            (Token::DotDotDot, _) => Ok(None),
+            (token, span) => Err(self.unexpected("an effect or ...", &token, span)),
+        }
+    }
 
-        match token{
-            tokenizer::Token::IdentNum(ident) => {
-                match ident.as_str(){
-                    "TITLE" => {
-                        file.title = expect_str(tokenizer.next())?;
-                        expect_nl(tokenizer.next())?;
+    fn read(mut self) -> Result<SoundFile, ParseError>{
+        let mut file = SoundFile::default();
+        while self.tokens.peek().is_some(){
+            let (token, span) = self.next("a command")?;
+            let ident = match token{
+                Token::IdentNum(ident) => ident,
+                Token::NewLine => continue,
+                token => return Err(self.unexpected("a command", &token, span)),
+            };
+            match ident.as_str(){
+                "TITLE" => file.title = self.expect_str()?,
+                "AUTHOR" => file.author = self.expect_str()?,
+                "COPYRIGHT" => file.copyright = self.expect_str()?,
+                "COMMENT" => file.comment = format!("{}{}\n", file.comment, self.expect_str()?),
+                "MACHINE" => file.machine = self.expect_dec()?,
+                "EXPANSION" => file.expansion = self.expect_dec()?,
+                "VIBRATO" => file.vibrato = self.expect_dec()?,
+                "SPLIT" => file.split = self.expect_dec()?,
+                "N163CHANNELS" => file.n163_channels = self.expect_dec()?,
+                "PLAYBACKRATE" => file.playbackrate = (self.expect_dec()?, self.expect_dec()?),
+                "TUNING" => file.tuning = (self.expect_dec()?, self.expect_dec()?),
+                "MACRO" => file.macros.push(self.read_macro(Chip::Apu)?),
+                "MACROVRC6" => file.macros.push(self.read_macro(Chip::Vrc6)?),
+                "MACRON163" => file.macros.push(self.read_macro(Chip::N163)?),
+                "MACROS5B" => file.macros.push(self.read_macro(Chip::S5B)?),
+                "DPCMDEF" => {
+                    let id = self.expect_dec()?;
+                    let (len, len_span) = self.expect_dec_span()?;
+                    let name = self.expect_str()?;
+                    self.expect_nl()?;
+                    let mut data = Vec::new();
+                    while self.next_is("DPCM"){
+                        self.expect_colon()?;
+                        data.extend(self.hex_list::<u8>()?);
+                        self.expect_nl()?;
                     }
-                    "AUTHOR" => {
-                        file.author = expect_str(tokenizer.next())?;
-                        expect_nl(tokenizer.next())?;
+                    if data.len() as i64 != len{
+                        let kind = ParseErrorKind::LengthMismatch{ what: "DPCMDEF", expected: len.max(0) as usize, found: data.len() };
+                        return Err(self.error(kind, len_span));
                     }
-                    "COPYRIGHT" => {
-                        file.copyright = expect_str(tokenizer.next())?;
-                        expect_nl(tokenizer.next())?;
-                    }
-                    "COMMENT" => {
-                        file.comment = format!("{}{}\n", file.comment, expect_str(tokenizer.next())?);
-                        expect_nl(tokenizer.next())?;
-                    }
-                    "MACHINE" => {
-                        file.machine = expect_dec_num(tokenizer.next())?.try_into()?;
-                        expect_nl(tokenizer.next())?;
-                    }
-                    "EXPANSION" => {
-                        file.expansion = expect_dec_num(tokenizer.next())?.try_into()?;
-                        expect_nl(tokenizer.next())?;
-                    }
-                    "VIBRATO" => {
-                        file.vibrato = expect_dec_num(tokenizer.next())?.try_into()?;
-                        expect_nl(tokenizer.next())?;
-                    }
-                    "SPLIT" => {
-                        file.split = expect_dec_num(tokenizer.next())?.try_into()?;
-                        expect_nl(tokenizer.next())?;
-                    }
-                    "PLAYBACKRATE" => {
-                        file.playbackrate.0 = expect_dec_num(tokenizer.next())?.try_into()?;
-                        file.playbackrate.1 = expect_dec_num(tokenizer.next())?.try_into()?;
-                        expect_nl(tokenizer.next())?;
-                    }
-                    "TUNING" => {
-                        file.tuning.0 = expect_dec_num(tokenizer.next())?;
-                        file.tuning.1 = expect_dec_num(tokenizer.next())?;
-                        expect_nl(tokenizer.next())?;
-                    }
-                    "MACRO" => {
-                        let mut song_macro = SongMacro{
-                            m_type:  expect_dec_num(tokenizer.next())?.try_into().unwrap(),
-                            m_id: expect_dec_num(tokenizer.next())?.try_into()?,
-                            m_loop: expect_opt_dec_num(tokenizer.next())?.try_into()?,
-                            m_release: expect_opt_dec_num(tokenizer.next())?.try_into()?,
-                            m_type_specific: expect_dec_num(tokenizer.next())?.try_into()?,
-                            vals: Default::default(),
-                        };
-                        expect_colon(tokenizer.next())?;
-                        while let Option::Some(tok) = tokenizer.next(){
-                            match tok{
-                                Token::IdentNum(num) => {
-                                    song_macro.vals.push(num.dec()?.try_into()?);
-                                },
-                                Token::NewLine => {break;},
-                                _ => {
-                                    return Err("Unexpected token type".into());
-                                }
-                            }
-                        }
-                        file.macros.push(song_macro);
-                    }
-                    "DPCMDEF" => {
-                        let id =  expect_dec_num(tokenizer.next())?.try_into()?;
-                        let len = expect_dec_num(tokenizer.next())? .try_into()?;
-                        let name = expect_str(tokenizer.next())?;
-                        expect_nl(tokenizer.next())?;
-                        let mut data = Vec::new();
-
-                        while let Option::Some(Token::IdentNum(str)) = tokenizer.peek_skipping_nl(){
-                            if str == "DPCM"{
-                                let _ = tokenizer.next();//accept peek
-                                expect_colon(tokenizer.next())?;
-                                while let Option::Some(Token::IdentNum(num)) = tokenizer.peek(){
-                                    data.push(num.hex()? as u8);
-                                    let _ = tokenizer.next();//accept peek
-                                }
-                                expect_nl(tokenizer.next())?
-                            }else{
-                                break;
-                            }
-                        }
-                        if data.len() != len{
-                            return Result::Err(format!("DPCMDEF provided data doesnt match size given, listed: {}, given: {}", len, data.len()).into());
-                        }
-                        file.dpcmdef.push(SongDpcmSamples { id, name, data });
-                    }
-                    "INST2A03" => {
-                        let inst = Inst2A03{
-                            id: expect_dec_num(tokenizer.next())?.try_into()?,
-                            vol_macro: expect_opt_dec_num(tokenizer.next())?,
-                            arp_macro: expect_opt_dec_num(tokenizer.next())?,
-                            pitch_macro: expect_opt_dec_num(tokenizer.next())?,
-                            high_pitch_macro: expect_opt_dec_num(tokenizer.next())?,
-                            duity_macro: expect_opt_dec_num(tokenizer.next())?,
-                            name: expect_str(tokenizer.next())?,
-                        };
-                        file.inst2a03.push(inst);
-                    }
-                    "KEYDPCM" => {
-                        let key = KeyDPCM{
-                            id: expect_dec_num(tokenizer.next())?.try_into()?,
-                            inst_id: expect_dec_num(tokenizer.next())?.try_into()?,
-                            midi_note: {
-                                let oct: u32 = expect_dec_num(tokenizer.next())?.try_into()?;
-                                let note: u32 = expect_dec_num(tokenizer.next())?.try_into()?;
-                                (oct + 1) * 12 + note
-                            },
-                            dpcm_id:  expect_dec_num(tokenizer.next())?.try_into()?,
-                            loop_key: {
-                                let num = expect_dec_num(tokenizer.next())?;
-                                if num == 0{
-                                    false
-                                }else if num == 1{
-                                    true
-                                }else{
-                                    return Err("Unexpected loop value, can only be 0 or 1".into());
-                                }
-                            },
-                            loop_point: expect_dec_num(tokenizer.next())?.try_into()?,
-                            d_counter: expect_opt_dec_num(tokenizer.next())?,
-                            
-                        };
-                        file.keydpcm.push(key);
+                    file.dpcmdef.push(SongDpcmSamples { id, name, data: data.into() });
+                    continue;
+                }
+                "INST2A03" => {
+                    file.instruments.push(Instrument::Apu(Inst2A03{
+                        id: self.expect_dec()?,
+                        vol_macro: self.expect_opt_dec()?,
+                        arp_macro: self.expect_opt_dec()?,
+                        pitch_macro: self.expect_opt_dec()?,
+                        high_pitch_macro: self.expect_opt_dec()?,
+                        duity_macro: self.expect_opt_dec()?,
+                        name: self.expect_str()?,
+                    }));
+                }
+                "INSTVRC6" => {
+                    file.instruments.push(Instrument::Vrc6(InstVRC6{
+                        id: self.expect_dec()?,
+                        vol_macro: self.expect_opt_dec()?,
+                        arp_macro: self.expect_opt_dec()?,
+                        pitch_macro: self.expect_opt_dec()?,
+                        high_pitch_macro: self.expect_opt_dec()?,
+                        duity_macro: self.expect_opt_dec()?,
+                        name: self.expect_str()?,
+                    }));
+                }
+                "INSTVRC7" => {
+                    let id = self.expect_dec()?;
+                    let patch = self.expect_dec()?;
+                    let mut custom = [0; 8];
+                    for register in custom.iter_mut(){
+                        *register = self.expect_hex()?;
                     }
-                    "TRACK" => {
-                        let mut track = Track{
-                            pattern_length: expect_dec_num(tokenizer.next())?.try_into()?,
-                            speed: expect_dec_num(tokenizer.next())?.try_into()?,
-                            temp: expect_dec_num(tokenizer.next())?.try_into()?,
-                            name: expect_str(tokenizer.next())?,
-                            comumns: Default::default(),
-                            patterns: Default::default(),
-                            pattern_order: Default::default(),
-                        };
-                        
-
-                        if let Option::Some(Token::IdentNum(val)) = tokenizer.peek_skipping_nl(){
-                            if val == "COLUMNS"{
-                                let _ = tokenizer.next();//accept peek
-                                expect_colon(tokenizer.next())?;
-                                while let Option::Some(Token::IdentNum(num)) = tokenizer.peek(){
-                                    track.comumns.push(num.hex()?.try_into()?);
-                                    let _ = tokenizer.next();//accept peek
-                                }
-                            }else{
-                                return Err("Missing COLUMNS after TRACK".into())
-                            }
-                        }else{
-                            return Err("Expected ident found other".into())
-                        }
-
-                        while let Option::Some(Token::IdentNum(val)) = tokenizer.peek_skipping_nl(){
-                            if val == "ORDER"{
-                                let mut order_data = Vec::new();
-                                
-                                let _ = tokenizer.next();//accept peek
-                                let id = expect_hex_num(tokenizer.next())?;
-                                expect_colon(tokenizer.next())?;
-                                while let Option::Some(Token::IdentNum(num)) = tokenizer.peek(){
-                                    order_data.push(num.hex()?.try_into()?);
-                                    let _ = tokenizer.next();//accept peek
-                                }
-                                expect_nl(tokenizer.next())?;
-                                if order_data.len() != track.comumns.len(){
-                                    return Err("Order data and column data cannot be different lengths??".into())
-                                }
-                                track.pattern_order.push((id.try_into()?, order_data));
-                            }else{
-                                break;
-                            }
-                        }
-
-                        //let mut patterns = Vec::new();
-                        while let Option::Some(Token::IdentNum(val)) = tokenizer.peek_skipping_nl(){
-                            if val == "PATTERN"{
-                                
-                                let _ = tokenizer.next();//accept peek
-                                let mut pattern = Pattern{
-                                    id: expect_hex_num(tokenizer.next())?.try_into()?,
-                                    rows: Default::default(),
-                                };
-                                
-                                while let Option::Some(Token::IdentNum(val)) = tokenizer.peek_skipping_nl(){
-                                    if val == "ROW"{
-                                        let _ = tokenizer.next();//accept peek
-                                        let mut row = Row{
-                                            id: expect_hex_num(tokenizer.next())?.try_into()?,
-                                            sheet_notes: Default::default(),
-                                        };
-                                        
-                                        for i in 0..track.comumns.len(){
-                                            expect_colon(tokenizer.next())?;
-                                            let mut sheet_note = SheetNote{
-                                                note: option_note(tokenizer.next())?,
-                                                inst:  option_instrament(tokenizer.next())?,
-                                                vol: option_volume(tokenizer.next())?,
-                                                efx: Default::default(),
-                                            };
-                                            for j in 0..track.comumns[i]{
-                                                sheet_note.efx[j as usize] = option_effect(tokenizer.next())?;
-                                            }
-                                            row.sheet_notes.push(sheet_note);
-                                        }
-
-                                        pattern.rows.push(row);
-                                    }else{
-                                        break;//return Err("Expected Ident ROW found other".into());
-                                    }
-                                }
-
-                                if pattern.rows.len() != track.pattern_length as usize{
-                                    return Err("Given pattern length doesn match actual pattern length".into());
-                                }
-                                track.patterns.push(pattern);
-                                //orders.push((id, order_data));
-                            }else{
-                                break;
-                            }
-                        }
-                        file.tracks.push(track);
-
+                    file.instruments.push(Instrument::Vrc7(InstVRC7{ id, patch, custom, name: self.expect_str()? }));
+                }
+                "INSTFDS" => {
+                    file.instruments.push(Instrument::Fds(InstFDS{
+                        id: self.expect_dec()?,
+                        mod_enable: self.expect_dec::<u8>()? != 0,
+                        mod_speed: self.expect_dec()?,
+                        mod_depth: self.expect_dec()?,
+                        mod_delay: self.expect_dec()?,
+                        wave: Default::default(),
+                        modulation: Default::default(),
+                        macros: Default::default(),
+                        name: self.expect_str()?,
+                    }));
+                }
+                "FDSWAVE" => {
+                    let (id, span) = self.expect_dec_span()?;
+                    self.expect_colon()?;
+                    let wave = self.dec_list(64, "FDSWAVE")?;
+                    self.fds_instrument(&mut file, id, span)?.wave = wave;
+                }
+                "FDSMOD" => {
+                    let (id, span) = self.expect_dec_span()?;
+                    self.expect_colon()?;
+                    let modulation = self.dec_list(32, "FDSMOD")?;
+                    self.fds_instrument(&mut file, id, span)?.modulation = modulation;
+                }
+                "FDSMACRO" => {
+                    let (id, span) = self.expect_dec_span()?;
+                    let mut song_macro = SongMacro{
+                        chip: Chip::Fds,
+                        m_type: self.expect_dec()?,
+                        m_id: self.fit(id, span)?,
+                        m_loop: self.expect_opt_dec()?,
+                        m_release: self.expect_opt_dec()?,
+                        m_type_specific: self.expect_dec()?,
+                        vals: Default::default(),
+                    };
+                    self.expect_colon()?;
+                    while let Some(Token::IdentNum(_)) = self.peek(){
+                        song_macro.vals.push(self.expect_dec()?);
                     }
-                    _ => {
-                        return Result::Err(format!("Unknown command: {}", ident.as_str()).into());
+                    self.fds_instrument(&mut file, id, span)?.macros.push(song_macro);
+                }
+                "INSTN163" => {
+                    file.instruments.push(Instrument::N163(InstN163{
+                        id: self.expect_dec()?,
+                        vol_macro: self.expect_opt_dec()?,
+                        arp_macro: self.expect_opt_dec()?,
+                        pitch_macro: self.expect_opt_dec()?,
+                        high_pitch_macro: self.expect_opt_dec()?,
+                        wave_macro: self.expect_opt_dec()?,
+                        wave_size: self.expect_dec()?,
+                        wave_pos: self.expect_dec()?,
+                        wave_count: self.expect_dec()?,
+                        waves: Default::default(),
+                        name: self.expect_str()?,
+                    }));
+                }
+                "N163WAVE" => {
+                    let (id, span) = self.expect_dec_span()?;
+                    let (index, index_span) = self.expect_dec_span()?;
+                    self.expect_colon()?;
+                    let inst = match file.instruments.iter_mut().find(|inst| inst.id() as i64 == id){
+                        Some(Instrument::N163(inst)) => inst,
+                        _ => return Err(self.error(ParseErrorKind::UndefinedInstrument(id), span)),
+                    };
+                    if index != inst.waves.len() as i64{
+                        let kind = ParseErrorKind::LengthMismatch{ what: "N163WAVE index", expected: inst.waves.len(), found: index.max(0) as usize };
+                        return Err(self.error(kind, index_span));
                     }
+                    let size = inst.wave_size as usize;
+                    inst.waves.push(self.dec_list(size, "N163WAVE")?);
                 }
-            },
-            tokenizer::Token::NewLine => {
-                //skip
-            },
-            tokenizer::Token::Empty => return Result::Err("Given empty??".into()),
-            tokenizer::Token::Error(char, location) => return Result::Err(format!("Unreconized char: {:?} at {:?}", char, location).into()),
+                "INSTS5B" => {
+                    file.instruments.push(Instrument::S5B(InstS5B{
+                        id: self.expect_dec()?,
+                        vol_macro: self.expect_opt_dec()?,
+                        arp_macro: self.expect_opt_dec()?,
+                        pitch_macro: self.expect_opt_dec()?,
+                        high_pitch_macro: self.expect_opt_dec()?,
+                        duity_macro: self.expect_opt_dec()?,
+                        name: self.expect_str()?,
+                    }));
+                }
+                "KEYDPCM" => {
+                    let inst_id = self.expect_dec()?;
+                    let octave: u32 = self.expect_dec()?;
+                    let note: u32 = self.expect_dec()?;
+                    let dpcm_id = self.expect_dec()?;
+                    let pitch = self.expect_dec()?;
+                    let loop_key = match self.expect_dec_span()?{
+                        (0, _) => false,
+                        (1, _) => true,
+                        (_, span) => return Err(self.out_of_range("0 or 1", span)),
+                    };
+                    file.keydpcm.push(KeyDPCM{
+                        inst_id,
+                        midi_note: (octave + 1) * 12 + note,
+                        dpcm_id,
+                        pitch,
+                        loop_key,
+                        loop_point: self.expect_dec()?,
+                        d_counter: self.expect_opt_dec()?,
+                    });
+                }
+                "TRACK" => {
+                    let chips = file.column_chips();
+                    file.tracks.push(self.read_track(&chips)?);
+                    continue;
+                }
+                _ => return Err(self.error(ParseErrorKind::UnknownCommand(ident.as_str().into()), span)),
+            }
+            self.expect_nl()?;
+        }
+        Ok(file)
+    }
+
+    /// decimal numbers until the end of the line, exactly `len` of them
+    fn dec_list<T: TryFrom<i64>>(&mut self, len: usize, what: &'static str) -> Result<Vec<T>, ParseError>{
+        let start = self.last;
+        let mut list = Vec::with_capacity(len);
+        while let Some(Token::IdentNum(_)) = self.peek(){
+            list.push(self.expect_dec()?);
+        }
+        if list.len() != len{
+            let kind = ParseErrorKind::LengthMismatch{ what, expected: len, found: list.len() };
+            return Err(self.error(kind, Span{ start: start.start, end: self.last.end }));
+        }
+        Ok(list)
+    }
+
+    /// the FDS instrument an FDSWAVE, FDSMOD or FDSMACRO line belongs to
+    fn fds_instrument<'f>(&self, file: &'f mut SoundFile, id: i64, span: Span) -> Result<&'f mut InstFDS, ParseError>{
+        match file.instruments.iter_mut().find(|inst| inst.id() as i64 == id){
+            Some(Instrument::Fds(inst)) => Ok(inst),
+            _ => Err(self.error(ParseErrorKind::UndefinedInstrument(id), span)),
+        }
+    }
+
+    /// `type id loop release setting : values`, shared by every chip's
+    /// macro command
+    fn read_macro(&mut self, chip: Chip) -> Result<SongMacro, ParseError>{
+        let mut song_macro = SongMacro{
+            chip,
+            m_type: self.expect_dec()?,
+            m_id: self.expect_dec()?,
+            m_loop: self.expect_opt_dec()?,
+            m_release: self.expect_opt_dec()?,
+            m_type_specific: self.expect_dec()?,
+            vals: Default::default(),
+        };
+        self.expect_colon()?;
+        while let Some(Token::IdentNum(_)) = self.peek(){
+            song_macro.vals.push(self.expect_dec()?);
+        }
+        Ok(song_macro)
+    }
+
+    /// a TRACK line and the COLUMNS, ORDER and PATTERN blocks under it,
+    /// `chips` gives the chip of each column EXPANSION enables
+    fn read_track(&mut self, chips: &[Chip]) -> Result<Track, ParseError>{
+        let pattern_length = self.expect_dec()?;
+        // the engine divides by the speed
+        let (speed, span) = self.expect_dec_span()?;
+        if speed < 1{
+            return Err(self.out_of_range("at least 1", span));
+        }
+        let mut track = Track{
+            pattern_length,
+            speed: self.fit(speed, span)?,
+            temp: self.expect_dec()?,
+            name: self.expect_str()?,
+            comumns: Default::default(),
+            patterns: Default::default(),
+            pattern_order: Default::default(),
+        };
+        self.expect_nl()?;
+
+        match self.peek_skipping_nl(){
+            Some(Token::IdentNum(val)) if val == "COLUMNS" => {
+                self.next("COLUMNS")?;
+            }
             _ => {
+                let (token, span) = self.next("COLUMNS")?;
+                return Err(self.unexpected("COLUMNS", &token, span));
+            }
+        }
+        self.expect_colon()?;
+        while let Some(Token::IdentNum(_)) = self.peek(){
+            let (token, span) = self.next("an effect column count")?;
+            let (columns, span) = self.hex(token, span, "an effect column count")?;
+            if columns < 1 || columns as usize > MAX_EFFECT_COLUMNS{
+                return Err(self.out_of_range(&format!("1 to {}", MAX_EFFECT_COLUMNS), span));
+            }
+            track.comumns.push(columns as u8);
+        }
+        if track.comumns.len() != chips.len(){
+            let kind = ParseErrorKind::LengthMismatch{ what: "COLUMNS", expected: chips.len(), found: track.comumns.len() };
+            return Err(self.error(kind, self.last));
+        }
+        self.expect_nl()?;
 
+        while self.next_is("ORDER"){
+            let id = self.expect_hex()?;
+            self.expect_colon()?;
+            let order_data: Vec<u8> = self.hex_list()?;
+            if order_data.len() != track.comumns.len(){
+                let kind = ParseErrorKind::LengthMismatch{ what: "ORDER", expected: track.comumns.len(), found: order_data.len() };
+                return Err(self.error(kind, self.last));
             }
+            self.expect_nl()?;
+            track.pattern_order.push((id, order_data));
         }
+
+        while self.next_is("PATTERN"){
+            let header = self.last;
+            let mut pattern = Pattern{
+                id: self.expect_hex()?,
+                rows: Default::default(),
+            };
+            self.expect_nl()?;
+            while self.next_is("ROW"){
+                pattern.rows.push(self.read_row(&track, chips)?);
+            }
+            if pattern.rows.len() != track.pattern_length as usize{
+                let kind = ParseErrorKind::LengthMismatch{ what: "PATTERN", expected: track.pattern_length as usize, found: pattern.rows.len() };
+                return Err(self.error(kind, Span{ start: header.start, end: self.last.end }));
+            }
+            track.patterns.push(pattern);
+        }
+        Ok(track)
     }
-    Result::Ok(file)
-}
 
+    fn read_row(&mut self, track: &Track, chips: &[Chip]) -> Result<Row, ParseError>{
+        let mut row = Row{
+            id: self.expect_hex()?,
+            sheet_notes: Vec::with_capacity(track.comumns.len()),
+        };
+        for (columns, chip) in track.comumns.iter().zip(chips){
+            self.expect_colon()?;
+            let mut sheet_note = SheetNote{
+                note: self.option_note()?,
+                inst: self.option_instrument()?,
+                vol: self.option_volume()?,
+                efx: Vec::with_capacity(*columns as usize),
+            };
+            for _ in 0..*columns{
+                sheet_note.efx.push(self.option_effect(*chip)?);
+            }
+            row.sheet_notes.push(sheet_note);
+        }
+        self.expect_nl()?;
+        Ok(row)
+    }
+}
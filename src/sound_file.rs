@@ -1,6 +1,8 @@
 
+use std::sync::Arc;
+
 #[allow(unused)]
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct SoundFile{
     pub title: String,
     pub author: String,
@@ -8,33 +10,35 @@ pub struct SoundFile{
     pub comment: String,
 
     pub machine: u32,
+    /// bitmask of `Chip::expansion_bit`s
     pub expansion: u32,
+    /// N163 channels in use, from N163CHANNELS
+    pub n163_channels: u32,
     pub vibrato: u32,
     pub split: u32,
     pub playbackrate: (u32, u32),
     pub tuning: (i32, i32),
 
     pub macros: Vec<SongMacro>,
-    pub inst2a03: Vec<Inst2A03>,
+    pub instruments: Vec<Instrument>,
     pub keydpcm: Vec<KeyDPCM>,
     pub dpcmdef: Vec<SongDpcmSamples>,
     pub tracks: Vec<Track>,
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct KeyDPCM{
-    pub id: u8,
     pub inst_id: u8,
     pub midi_note: u32,
-    //_3: Option<u8>,
     pub dpcm_id: u8,
+    pub pitch: u8,
     pub loop_key: bool,
     pub loop_point: u8,
     pub d_counter: Option<u8>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Inst2A03{
     pub id: u8,
     pub vol_macro: Option<u8>,
@@ -45,7 +49,134 @@ pub struct Inst2A03{
     pub name: String,
 }
 
-#[derive(Debug)]
+/// Same macro slots as the 2A03, the duty macro sets the pulse width.
+#[derive(Debug, Clone)]
+pub struct InstVRC6{
+    pub id: u8,
+    pub vol_macro: Option<u8>,
+    pub arp_macro: Option<u8>,
+    pub pitch_macro: Option<u8>,
+    pub high_pitch_macro: Option<u8>,
+    pub duity_macro: Option<u8>,
+    pub name: String,
+}
+
+/// An FM patch, 0 is the custom patch in `custom`.
+#[derive(Debug, Clone)]
+pub struct InstVRC7{
+    pub id: u8,
+    pub patch: u8,
+    /// registers $00-$07 of the custom patch
+    pub custom: [u8; 8],
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct InstFDS{
+    pub id: u8,
+    pub mod_enable: bool,
+    pub mod_speed: u16,
+    pub mod_depth: u8,
+    pub mod_delay: u8,
+    /// FDSWAVE, 64 samples of 0-63
+    pub wave: Vec<u8>,
+    /// FDSMOD, 32 modulation steps of 0-7
+    pub modulation: Vec<u8>,
+    /// FDSMACRO, volume, arpeggio and pitch macros owned by the instrument
+    pub macros: Vec<SongMacro>,
+    pub name: String,
+}
+
+/// The duty macro slot holds the wave index macro.
+#[derive(Debug, Clone)]
+pub struct InstN163{
+    pub id: u8,
+    pub vol_macro: Option<u8>,
+    pub arp_macro: Option<u8>,
+    pub pitch_macro: Option<u8>,
+    pub high_pitch_macro: Option<u8>,
+    pub wave_macro: Option<u8>,
+    /// samples per wave
+    pub wave_size: u8,
+    /// where in wave RAM the wave is loaded, in samples
+    pub wave_pos: u8,
+    pub wave_count: u8,
+    /// N163WAVE, `wave_size` samples of 0-15 each
+    pub waves: Vec<Vec<u8>>,
+    pub name: String,
+}
+
+/// The duty macro slot sets the tone and noise mix.
+#[derive(Debug, Clone)]
+pub struct InstS5B{
+    pub id: u8,
+    pub vol_macro: Option<u8>,
+    pub arp_macro: Option<u8>,
+    pub pitch_macro: Option<u8>,
+    pub high_pitch_macro: Option<u8>,
+    pub duity_macro: Option<u8>,
+    pub name: String,
+}
+
+/// An instrument of any chip, MMC5 channels play 2A03 instruments.
+#[derive(Debug, Clone)]
+pub enum Instrument{
+    Apu(Inst2A03),
+    Vrc6(InstVRC6),
+    Vrc7(InstVRC7),
+    Fds(InstFDS),
+    N163(InstN163),
+    S5B(InstS5B),
+}
+
+impl Instrument{
+    pub fn id(&self) -> u8{
+        match self{
+            Instrument::Apu(inst) => inst.id,
+            Instrument::Vrc6(inst) => inst.id,
+            Instrument::Vrc7(inst) => inst.id,
+            Instrument::Fds(inst) => inst.id,
+            Instrument::N163(inst) => inst.id,
+            Instrument::S5B(inst) => inst.id,
+        }
+    }
+
+    pub fn name(&self) -> &str{
+        match self{
+            Instrument::Apu(inst) => &inst.name,
+            Instrument::Vrc6(inst) => &inst.name,
+            Instrument::Vrc7(inst) => &inst.name,
+            Instrument::Fds(inst) => &inst.name,
+            Instrument::N163(inst) => &inst.name,
+            Instrument::S5B(inst) => &inst.name,
+        }
+    }
+
+    pub fn chip(&self) -> Chip{
+        match self{
+            Instrument::Apu(_) => Chip::Apu,
+            Instrument::Vrc6(_) => Chip::Vrc6,
+            Instrument::Vrc7(_) => Chip::Vrc7,
+            Instrument::Fds(_) => Chip::Fds,
+            Instrument::N163(_) => Chip::N163,
+            Instrument::S5B(_) => Chip::S5B,
+        }
+    }
+
+    /// ids of the volume, arpeggio, pitch, hi-pitch and duty macros, in
+    /// the chip's MACRO numbering, for instruments that use them
+    pub fn macro_ids(&self) -> Option<[Option<u8>; 5]>{
+        match self{
+            Instrument::Apu(inst) => Some([inst.vol_macro, inst.arp_macro, inst.pitch_macro, inst.high_pitch_macro, inst.duity_macro]),
+            Instrument::Vrc6(inst) => Some([inst.vol_macro, inst.arp_macro, inst.pitch_macro, inst.high_pitch_macro, inst.duity_macro]),
+            Instrument::N163(inst) => Some([inst.vol_macro, inst.arp_macro, inst.pitch_macro, inst.high_pitch_macro, inst.wave_macro]),
+            Instrument::S5B(inst) => Some([inst.vol_macro, inst.arp_macro, inst.pitch_macro, inst.high_pitch_macro, inst.duity_macro]),
+            Instrument::Vrc7(_) | Instrument::Fds(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Track{
     pub pattern_length: u32,
     pub speed: u32,
@@ -57,36 +188,39 @@ pub struct Track{
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Pattern{
     pub id: u8,
     pub rows: Vec<Row>
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Row{
     pub id: u8,
     pub sheet_notes: Vec<SheetNote>
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SheetNote{
     pub note: Option<Note>,
     pub inst: Option<u8>,
     pub vol: Option<u8>,
-    pub efx: [Option<Effect>; 3]
+    /// one per effect column the track declares for this channel
+    pub efx: Vec<Option<Effect>>
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SongDpcmSamples{
     pub id: u8,
     pub name: String,
-    pub data: Vec<u8>
+    pub data: Arc<[u8]>
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SongMacro{
+    /// macros are numbered separately for each chip
+    pub chip: Chip,
     pub m_type: u8,
     pub m_id: u8,
     pub m_loop: Option<u8>,
@@ -104,7 +238,68 @@ pub enum Note{
     Release,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// FamiTracker shows at most this many effect columns per channel
+pub const MAX_EFFECT_COLUMNS: usize = 4;
+
+/// The sound chips a module can use, the 2A03 is always present.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip{
+    Apu,
+    Vrc6,
+    Vrc7,
+    Fds,
+    Mmc5,
+    N163,
+    S5B,
+}
+
+impl Chip{
+    /// in the order FamiTracker lays out their track columns
+    pub const ALL: [Chip; 7] = [Chip::Apu, Chip::Vrc6, Chip::Vrc7, Chip::Fds, Chip::Mmc5, Chip::N163, Chip::S5B];
+
+    /// the chip's bit in EXPANSION, 0 for the always present 2A03
+    pub fn expansion_bit(&self) -> u32{
+        match self{
+            Chip::Apu => 0,
+            Chip::Vrc6 => 1,
+            Chip::Vrc7 => 2,
+            Chip::Fds => 4,
+            Chip::Mmc5 => 8,
+            Chip::N163 => 16,
+            Chip::S5B => 32,
+        }
+    }
+}
+
+impl SoundFile{
+    pub fn instrument(&self, id: u8) -> Option<&Instrument>{
+        self.instruments.iter().find(|inst| inst.id() == id)
+    }
+
+    pub fn uses_chip(&self, chip: Chip) -> bool{
+        chip == Chip::Apu || self.expansion & chip.expansion_bit() != 0
+    }
+
+    /// the chip behind each track column
+    pub fn column_chips(&self) -> Vec<Chip>{
+        let mut columns = Vec::new();
+        for chip in Chip::ALL.iter().filter(|chip| self.uses_chip(**chip)){
+            let count = match chip{
+                Chip::Apu => 5,
+                Chip::Vrc6 => 3,
+                Chip::Vrc7 => 6,
+                Chip::Fds => 1,
+                Chip::Mmc5 => 2,
+                Chip::N163 => self.n163_channels.clamp(1, 8) as usize,
+                Chip::S5B => 3,
+            };
+            columns.extend(std::iter::repeat_n(*chip, count));
+        }
+        columns
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Effect{
     Arpeggio(u8, u8),
     PitchSlideUp(Option<u8>),
@@ -116,103 +311,261 @@ pub enum Effect{
     JumpToPattern(u8),
     Halt,
     SkipFrameStartAtRow(u8),
+    Volume(u8),
     SpeedOrTempo(Option<u8>, Option<u8>),
     NoteDelay(u8),
     HardwareSweepUp(u8, u8),
     HardwareSweepDown(u8, u8),
-    FSDModulationDepth,
-    FDSModulationSpeed,
+    FSDModulationDepth(u8),
+    FDSModulationSpeedHigh(u8),
+    FDSModulationSpeedLow(u8),
+    SunsoftEnvelopeLow(u8),
+    SunsoftEnvelopeHigh(u8),
+    SunsoftEnvelopeShape(u8),
     FinePitch(u8),
     NoteSlideUp(u8, u8),
     NoteSlideDown(u8, u8),
     MuteDelay(u8),
-    AquareDuityNoiseN163Mode(/*TODO */),
+    /// Vxx, the pulse duty, the noise mode or the N163 wave index
+    AquareDuityNoiseN163Mode(u8),
     DPCMSampleSpeedOverride(u8),
+    DPCMRetrigger(u8),
     DPCMSampleOffset(u32),
     DPCMDeltaCounter(u8),
 }
 
-impl TryFrom<&str> for Effect{
-    type Error = Box<dyn std::error::Error>;
+/// Effect variants without their parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EffectType{
+    Arpeggio,
+    PitchSlideUp,
+    PitchSlideDown,
+    AutomaticPortamento,
+    Vibrato,
+    Tremolo,
+    VolumeSlide,
+    JumpToPattern,
+    Halt,
+    SkipFrameStartAtRow,
+    Volume,
+    SpeedOrTempo,
+    NoteDelay,
+    HardwareSweepUp,
+    HardwareSweepDown,
+    FDSModulationDepth,
+    FDSModulationSpeedHigh,
+    FDSModulationSpeedLow,
+    SunsoftEnvelopeLow,
+    SunsoftEnvelopeHigh,
+    SunsoftEnvelopeShape,
+    FinePitch,
+    NoteSlideUp,
+    NoteSlideDown,
+    MuteDelay,
+    DutyNoiseMode,
+    DPCMSampleSpeedOverride,
+    DPCMRetrigger,
+    DPCMSampleOffset,
+    DPCMDeltaCounter,
+}
 
-    fn try_from(str: &str) -> Result<Self, Self::Error> {
-        let char = str.as_bytes()[0] as char;
-        let num_str = &str[1..3];
-        macro_rules! num {
-            () => {
-                u8::from_str_radix(num_str, 16)?
-            };
-        }
-        macro_rules! num_or_default {
-            ($default:expr) => {
-                match u8::from_str_radix(num_str, 16){
-                    Ok(val) => val,
-                    Err(_) => $default,
-                }
-            };
-        }
-        macro_rules! num_x_y {
-            () => {
-                {
-                    let num = u8::from_str_radix(num_str, 16)?;
-                    let x = num >> 4;
-                    let y = num & 0b1111;
-                    (x,y) 
-                }       
-            };
-        }
+/// FamiTracker's effect letters. A letter means the chip specific entry on
+/// that chip's channels and the `None` entry everywhere else.
+pub const EFFECT_TABLE: [(char, Option<Chip>, EffectType); 30] = [
+    ('H', Some(Chip::Fds), EffectType::FDSModulationDepth),
+    ('I', Some(Chip::Fds), EffectType::FDSModulationSpeedHigh),
+    ('J', Some(Chip::Fds), EffectType::FDSModulationSpeedLow),
+    ('H', Some(Chip::S5B), EffectType::SunsoftEnvelopeLow),
+    ('I', Some(Chip::S5B), EffectType::SunsoftEnvelopeHigh),
+    ('J', Some(Chip::S5B), EffectType::SunsoftEnvelopeShape),
+    ('0', None, EffectType::Arpeggio),
+    ('1', None, EffectType::PitchSlideUp),
+    ('2', None, EffectType::PitchSlideDown),
+    ('3', None, EffectType::AutomaticPortamento),
+    ('4', None, EffectType::Vibrato),
+    ('7', None, EffectType::Tremolo),
+    ('A', None, EffectType::VolumeSlide),
+    ('B', None, EffectType::JumpToPattern),
+    ('C', None, EffectType::Halt),
+    ('D', None, EffectType::SkipFrameStartAtRow),
+    ('E', None, EffectType::Volume),
+    ('F', None, EffectType::SpeedOrTempo),
+    ('G', None, EffectType::NoteDelay),
+    ('H', None, EffectType::HardwareSweepUp),
+    ('I', None, EffectType::HardwareSweepDown),
+    ('P', None, EffectType::FinePitch),
+    ('Q', None, EffectType::NoteSlideUp),
+    ('R', None, EffectType::NoteSlideDown),
+    ('S', None, EffectType::MuteDelay),
+    ('V', None, EffectType::DutyNoiseMode),
+    ('W', None, EffectType::DPCMSampleSpeedOverride),
+    ('X', None, EffectType::DPCMRetrigger),
+    ('Y', None, EffectType::DPCMSampleOffset),
+    ('Z', None, EffectType::DPCMDeltaCounter),
+];
 
-        macro_rules! num_option {
-            () => {
-                {
-                    let num = u8::from_str_radix(num_str, 16)?;
-                    if num == 0 {Option::None} else {Option::Some(num)}        
-                }
-            };
-        }
-        
-        match char{
-            '0' => Ok(Effect::Arpeggio(num_x_y!().0, num_x_y!().1)),
-            '1' => Ok(Effect::PitchSlideUp(num_option!())),
-            '2' => Ok(Effect::PitchSlideDown(num_option!())),
-            '3' => Ok(Effect::AutomaticPortamento(num_option!())),
-            '4' => Ok(Effect::Arpeggio(num_x_y!().0, num_x_y!().1)),
-            '7' => Ok(Effect::VibratoEffect(if num_x_y!().0 == 0 {None} else {Some(num_x_y!())})),
-            'A' => {
-                if 0 == num_x_y!().0 {
-                    return Ok(Effect::VolumeSlide(false, num_x_y!().1))
-                } else if  0 == num_x_y!().1 {
-                    return Ok(Effect::VolumeSlide(true, num_x_y!().0))
-                } else {
-                    return Err("Invalid slide num".into())
-                }
+impl EffectType{
+    /// what `letter` means on a channel of `chip`
+    pub fn from_letter(letter: char, chip: Chip) -> Option<Self>{
+        EFFECT_TABLE.iter()
+            .find(|(c, scope, _)| *c == letter && *scope == Some(chip))
+            .or_else(|| EFFECT_TABLE.iter().find(|(c, scope, _)| *c == letter && scope.is_none()))
+            .map(|(_, _, effect_type)| *effect_type)
+    }
+
+    pub fn letter(&self) -> char{
+        EFFECT_TABLE.iter()
+            .find(|(_, _, effect_type)| effect_type == self)
+            .map(|(letter, _, _)| *letter)
+            .unwrap_or('?')
+    }
+}
+
+impl Effect{
+    /// reads an effect like `4A3` on a channel of `chip`
+    pub fn parse(str: &str, chip: Chip) -> Result<Self, Box<dyn std::error::Error>>{
+        let mut chars = str.chars();
+        let (letter, param) = match (chars.next(), chars.as_str()){
+            (Some(letter), param) if param.len() == 2 => (letter, u8::from_str_radix(param, 16)?),
+            _ => return Err(format!("Invalid effect {:?}", str).into()),
+        };
+        let effect_type = EffectType::from_letter(letter, chip)
+            .ok_or_else(|| format!("Unknown effect {:?}", str))?;
+        Self::from_param(effect_type, param)
+    }
+
+    /// builds an effect from its type and the two hex digits after its letter
+    pub fn from_param(effect_type: EffectType, param: u8) -> Result<Self, Box<dyn std::error::Error>>{
+        let (x, y) = (param >> 4, param & 0x0F);
+        let option = if param == 0 { None } else { Some(param) };
+        Ok(match effect_type{
+            EffectType::Arpeggio => Effect::Arpeggio(x, y),
+            EffectType::PitchSlideUp => Effect::PitchSlideUp(option),
+            EffectType::PitchSlideDown => Effect::PitchSlideDown(option),
+            EffectType::AutomaticPortamento => Effect::AutomaticPortamento(option),
+            EffectType::Vibrato => Effect::VibratoEffect(if x == 0 { None } else { Some((x, y)) }),
+            EffectType::Tremolo => Effect::TremoloEffect(if x == 0 { None } else { Some((x, y)) }),
+            EffectType::VolumeSlide => match (x, y){
+                (0, y) => Effect::VolumeSlide(false, y),
+                (x, 0) => Effect::VolumeSlide(true, x),
+                _ => return Err("Invalid slide num".into()),
             },
-            'B' => Ok(Effect::JumpToPattern(num!())),
-            'C' => Ok(Effect::Halt),
-            'D' => Ok(Effect::SkipFrameStartAtRow(num!())),
-            //'E' => Ok(Effect::JumpToPattern(num!())),
-            'F' => {
-                let num = num!();
-                match num {
-                    0x00..=0x1F => Ok(Effect::SpeedOrTempo(Option::Some(num), None)),
-                    0x20..=0xFF => Ok(Effect::SpeedOrTempo(None, Some(num))),
-                }
+            EffectType::JumpToPattern => Effect::JumpToPattern(param),
+            EffectType::Halt => Effect::Halt,
+            EffectType::SkipFrameStartAtRow => Effect::SkipFrameStartAtRow(param),
+            EffectType::Volume => Effect::Volume(param),
+            EffectType::SpeedOrTempo => match param{
+                0x00..=0x1F => Effect::SpeedOrTempo(Some(param), None),
+                0x20..=0xFF => Effect::SpeedOrTempo(None, Some(param)),
             },
-            'G' => Ok(Effect::NoteDelay(num!())),
-            'H' => Ok(Effect::HardwareSweepUp(num_x_y!().0, num_x_y!().1)),
-            'I' => Ok(Effect::HardwareSweepDown(num_x_y!().0, num_x_y!().1)),
-            'P' => Ok(Effect::FinePitch(num_or_default!(0x80))),
-            'Q' => Ok(Effect::NoteSlideUp(num_x_y!().0, num_x_y!().1)),
-            'R' => Ok(Effect::NoteSlideDown(num_x_y!().0, num_x_y!().1)),
-            'S' => Ok(Effect::MuteDelay(num!())),
-            'V' => Ok(Effect::MuteDelay(num!())),
-            'W' => Ok(Effect::AquareDuityNoiseN163Mode()),
-            'X' => Ok(Effect::DPCMSampleSpeedOverride(num!())),
-            'Y' => Ok(Effect::DPCMSampleOffset(num!() as u32 * 64)),
-            'Z' => Ok(Effect::DPCMDeltaCounter(num!())),
-            _ => {
-                Result::Err("Unknown Effect number".into())
+            EffectType::NoteDelay => Effect::NoteDelay(param),
+            EffectType::HardwareSweepUp => Effect::HardwareSweepUp(x, y),
+            EffectType::HardwareSweepDown => Effect::HardwareSweepDown(x, y),
+            EffectType::FDSModulationDepth => Effect::FSDModulationDepth(param),
+            EffectType::FDSModulationSpeedHigh => Effect::FDSModulationSpeedHigh(param),
+            EffectType::FDSModulationSpeedLow => Effect::FDSModulationSpeedLow(param),
+            EffectType::SunsoftEnvelopeLow => Effect::SunsoftEnvelopeLow(param),
+            EffectType::SunsoftEnvelopeHigh => Effect::SunsoftEnvelopeHigh(param),
+            EffectType::SunsoftEnvelopeShape => Effect::SunsoftEnvelopeShape(param),
+            EffectType::FinePitch => Effect::FinePitch(param),
+            EffectType::NoteSlideUp => Effect::NoteSlideUp(x, y),
+            EffectType::NoteSlideDown => Effect::NoteSlideDown(x, y),
+            EffectType::MuteDelay => Effect::MuteDelay(param),
+            EffectType::DutyNoiseMode => Effect::AquareDuityNoiseN163Mode(param),
+            EffectType::DPCMSampleSpeedOverride => Effect::DPCMSampleSpeedOverride(param),
+            EffectType::DPCMRetrigger => Effect::DPCMRetrigger(param),
+            EffectType::DPCMSampleOffset => Effect::DPCMSampleOffset(param as u32 * 64),
+            EffectType::DPCMDeltaCounter => Effect::DPCMDeltaCounter(param),
+        })
+    }
+
+    pub fn effect_type(&self) -> EffectType{
+        match self{
+            Effect::Arpeggio(..) => EffectType::Arpeggio,
+            Effect::PitchSlideUp(_) => EffectType::PitchSlideUp,
+            Effect::PitchSlideDown(_) => EffectType::PitchSlideDown,
+            Effect::AutomaticPortamento(_) => EffectType::AutomaticPortamento,
+            Effect::VibratoEffect(_) => EffectType::Vibrato,
+            Effect::TremoloEffect(_) => EffectType::Tremolo,
+            Effect::VolumeSlide(..) => EffectType::VolumeSlide,
+            Effect::JumpToPattern(_) => EffectType::JumpToPattern,
+            Effect::Halt => EffectType::Halt,
+            Effect::SkipFrameStartAtRow(_) => EffectType::SkipFrameStartAtRow,
+            Effect::Volume(_) => EffectType::Volume,
+            Effect::SpeedOrTempo(..) => EffectType::SpeedOrTempo,
+            Effect::NoteDelay(_) => EffectType::NoteDelay,
+            Effect::HardwareSweepUp(..) => EffectType::HardwareSweepUp,
+            Effect::HardwareSweepDown(..) => EffectType::HardwareSweepDown,
+            Effect::FSDModulationDepth(_) => EffectType::FDSModulationDepth,
+            Effect::FDSModulationSpeedHigh(_) => EffectType::FDSModulationSpeedHigh,
+            Effect::FDSModulationSpeedLow(_) => EffectType::FDSModulationSpeedLow,
+            Effect::SunsoftEnvelopeLow(_) => EffectType::SunsoftEnvelopeLow,
+            Effect::SunsoftEnvelopeHigh(_) => EffectType::SunsoftEnvelopeHigh,
+            Effect::SunsoftEnvelopeShape(_) => EffectType::SunsoftEnvelopeShape,
+            Effect::FinePitch(_) => EffectType::FinePitch,
+            Effect::NoteSlideUp(..) => EffectType::NoteSlideUp,
+            Effect::NoteSlideDown(..) => EffectType::NoteSlideDown,
+            Effect::MuteDelay(_) => EffectType::MuteDelay,
+            Effect::AquareDuityNoiseN163Mode(_) => EffectType::DutyNoiseMode,
+            Effect::DPCMSampleSpeedOverride(_) => EffectType::DPCMSampleSpeedOverride,
+            Effect::DPCMRetrigger(_) => EffectType::DPCMRetrigger,
+            Effect::DPCMSampleOffset(_) => EffectType::DPCMSampleOffset,
+            Effect::DPCMDeltaCounter(_) => EffectType::DPCMDeltaCounter,
+        }
+    }
+
+    /// the two hex digits written after the effect's letter
+    pub fn param(&self) -> u8{
+        let x_y = |x: u8, y: u8| (x << 4) | (y & 0x0F);
+        match *self{
+            Effect::Arpeggio(x, y)
+            | Effect::HardwareSweepUp(x, y)
+            | Effect::HardwareSweepDown(x, y)
+            | Effect::NoteSlideUp(x, y)
+            | Effect::NoteSlideDown(x, y) => x_y(x, y),
+            Effect::PitchSlideUp(param)
+            | Effect::PitchSlideDown(param)
+            | Effect::AutomaticPortamento(param) => param.unwrap_or(0),
+            Effect::VibratoEffect(param) | Effect::TremoloEffect(param) => {
+                param.map_or(0, |(x, y)| x_y(x, y))
             }
+            Effect::VolumeSlide(true, amount) => amount << 4,
+            Effect::VolumeSlide(false, amount) => amount & 0x0F,
+            Effect::Halt => 0,
+            Effect::SpeedOrTempo(speed, tempo) => tempo.or(speed).unwrap_or(0),
+            Effect::DPCMSampleOffset(offset) => (offset / 64) as u8,
+            Effect::JumpToPattern(param)
+            | Effect::SkipFrameStartAtRow(param)
+            | Effect::Volume(param)
+            | Effect::NoteDelay(param)
+            | Effect::FSDModulationDepth(param)
+            | Effect::FDSModulationSpeedHigh(param)
+            | Effect::FDSModulationSpeedLow(param)
+            | Effect::SunsoftEnvelopeLow(param)
+            | Effect::SunsoftEnvelopeHigh(param)
+            | Effect::SunsoftEnvelopeShape(param)
+            | Effect::FinePitch(param)
+            | Effect::MuteDelay(param)
+            | Effect::AquareDuityNoiseN163Mode(param)
+            | Effect::DPCMSampleSpeedOverride(param)
+            | Effect::DPCMRetrigger(param)
+            | Effect::DPCMDeltaCounter(param) => param,
         }
     }
-}
\ No newline at end of file
+}
+
+/// writes the effect the way FamiTracker's text export does
+impl std::fmt::Display for Effect{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{:02X}", self.effect_type().letter(), self.param())
+    }
+}
+
+/// parses with the 2A03 meaning of each letter
+impl TryFrom<&str> for Effect{
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(str: &str) -> Result<Self, Self::Error> {
+        Effect::parse(str, Chip::Apu)
+    }
+}
@@ -1,11 +1,13 @@
-use std::{iter::Peekable, str::Chars, ops::Add, error::Error};
+use std::{iter::Peekable, str::Chars, ops::Add, error::Error, num::ParseIntError};
 
-use crate::sound_file::{Note, Effect};
+use crate::sound_file::{Note, Effect, Chip};
 
 enum TokenizerState{
     Default,
     Comment,
     Ident,
+    /// an ident that reached a digit, like N163WAVE or P80
+    IdentDigits,
     String,
     Dot,
     DotDot,
@@ -25,6 +27,36 @@ pub struct Location{
     column: usize,
 }
 
+impl Location{
+    /// 1 based line number
+    pub fn line(&self) -> usize{
+        self.line + 1
+    }
+
+    /// 1 based column, counted in chars
+    pub fn column(&self) -> usize{
+        self.column + 1
+    }
+
+    /// byte offset into the source
+    pub fn offset(&self) -> usize{
+        self.real
+    }
+}
+
+/// The source text a token came from, `end` is exclusive.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Span{
+    pub start: Location,
+    pub end: Location,
+}
+
+impl Span{
+    pub fn slice<'a>(&self, source: &'a str) -> &'a str{
+        &source[self.start.real..self.end.real]
+    }
+}
+
 impl Add<char> for Location{
     type Output = Self;
 
@@ -50,7 +82,7 @@ pub struct Tokenizer<'a>{
 }
 
 impl<'a> Tokenizer<'a>{
-    pub fn from_str(str: &'a str) -> Self{
+    pub fn new(str: &'a str) -> Self{
         Self{
             iter: str.chars().peekable(),
             str,
@@ -71,7 +103,7 @@ impl<'a> Tokenizer<'a>{
 }
 
 impl<'a> Iterator for Tokenizer<'a>{
-    type Item = Token;
+    type Item = (Token, Span);
 
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -146,7 +178,7 @@ impl<'a> Iterator for Tokenizer<'a>{
                             //continue
                         }
                         '0'..='9' => {
-                            self.state = TokenizerState::Number;
+                            self.state = TokenizerState::IdentDigits;
                         }
                         '-'|'#' => {
                             self.state = TokenizerState::Note;
@@ -158,6 +190,16 @@ impl<'a> Iterator for Tokenizer<'a>{
                         }
                     }
                 },
+                TokenizerState::IdentDigits => {
+                    match c{
+                        'a'..='z'|'A'..='Z'|'0'..='9' => {}
+                        _ => {
+                            self.state = TokenizerState::Default;
+                            ret = Option::Some(Token::IdentNum(IdentNum::new(self.str_last())));
+                            consume = false;
+                        }
+                    }
+                },
                 TokenizerState::String => {
                     match c{
                         '"' => {
@@ -206,7 +248,7 @@ impl<'a> Iterator for Tokenizer<'a>{
                         _ => {
                             self.state = TokenizerState::Default;
                             let str = self.str_last();
-                            ret = Option::Some(Token::IdentNum(IdentNum::from_str(str)));
+                            ret = Option::Some(Token::IdentNum(IdentNum::new(str)));
                             consume = false;
                         }
                     }
@@ -230,52 +272,25 @@ impl<'a> Iterator for Tokenizer<'a>{
                             let str = self.str_loc_loc(self.start, current);
                             let off = (str.as_bytes()[2] as u32 - '0' as u32 + 1) * 12;
                             let note = &str[0..2];
-                            loop{
-                                let note = match note{
-                                    "C-" => {
-                                        0
-                                    }
-                                    "C#" => {
-                                        1
-                                    }
-                                    "D-" => {
-                                        2
-                                    }
-                                    "D#" => {
-                                        3
-                                    }
-                                    "E-" => {
-                                        4
-                                    }
-                                    "F-" => {
-                                        5
-                                    }
-                                    "F#" => {
-                                        6
-                                    }
-                                    "G-" => {
-                                        7
-                                    }
-                                    "G#" => {
-                                        8
-                                    }
-                                    "A-" => {
-                                        9
-                                    }
-                                    "A#" => {
-                                        10
-                                    }
-                                    "B-" => {
-                                        11
-                                    }
-                                    _ => {
-                                        ret = Option::Some(Token::Error(note.chars().next().unwrap(), self.start));
-                                        break;
-                                    }
-                                };
-                                ret = Option::Some(Token::Note(Note::Midi(off + note)));
-                                break;
-                            }
+                            let semitone = match note{
+                                "C-" => Some(0),
+                                "C#" => Some(1),
+                                "D-" => Some(2),
+                                "D#" => Some(3),
+                                "E-" => Some(4),
+                                "F-" => Some(5),
+                                "F#" => Some(6),
+                                "G-" => Some(7),
+                                "G#" => Some(8),
+                                "A-" => Some(9),
+                                "A#" => Some(10),
+                                "B-" => Some(11),
+                                _ => None,
+                            };
+                            ret = Option::Some(match semitone{
+                                Some(semitone) => Token::Note(Note::Midi(off + semitone)),
+                                None => Token::Error(note.chars().next().unwrap(), self.start),
+                            });
                         }
                         '#' => {
                             self.state = TokenizerState::Default;
@@ -284,10 +299,10 @@ impl<'a> Iterator for Tokenizer<'a>{
                             let c = iter.next().unwrap().to_uppercase().next().unwrap();
                             match c {
                                 'A'..='F' => {
-                                    ret = Option::Some(Token::Note(Note::Hex(c as u8 - 'A' as u8 + 10)));
+                                    ret = Option::Some(Token::Note(Note::Hex(c as u8 - b'A' + 10)));
                                 }
                                 '0'..='9' => {
-                                    ret = Option::Some(Token::Note(Note::Hex(c as u8 - '0' as u8)));
+                                    ret = Option::Some(Token::Note(Note::Hex(c as u8 - b'0')));
                                 }
                                 _ => {
                                     ret = Option::Some(Token::Error(c, self.start));
@@ -350,15 +365,13 @@ impl<'a> Iterator for Tokenizer<'a>{
                 let _ = self.iter.next().unwrap();
                 self.last = current;
             }
-            match ret{
-                Some(val) => {
-                    self.start = self.last;
-                    match val{
-                        Token::Empty => {}
-                        _ => {return Option::Some(val)}
-                    }
-                },
-                None => {},
+            if let Some(val) = ret{
+                let span = Span{ start: self.start, end: self.last };
+                self.start = self.last;
+                match val{
+                    Token::Empty => {}
+                    _ => {return Option::Some((val, span))}
+                }
             }
         }
     }
@@ -370,10 +383,10 @@ pub trait SkipNLPeekable{
 
 impl<'a> SkipNLPeekable for Peekable<Tokenizer<'a>> {
     fn peek_skipping_nl(&mut self) -> Option<&Token> {
-        while let Option::Some(Token::NewLine) = self.peek(){
+        while let Option::Some((Token::NewLine, _)) = self.peek(){
             let _ = self.next();
         }
-        self.peek()
+        self.peek().map(|(token, _)| token)
     }
 }
 
@@ -397,19 +410,19 @@ pub struct IdentNum{
 }
 
 impl IdentNum{
-    pub fn hex(&self) -> Result<u32, Box<dyn Error>>{
-        Result::Ok(u32::from_str_radix(self.string.as_str(), 16)?)
+    pub fn hex(&self) -> Result<u32, ParseIntError>{
+        u32::from_str_radix(self.string.as_str(), 16)
     }
 
-    pub fn dec(&self) -> Result<i32, Box<dyn Error>>{
-        Result::Ok(i32::from_str_radix(self.string.as_str(), 10)?)
+    pub fn dec(&self) -> Result<i32, ParseIntError>{
+        self.string.parse()
     }
 
-    pub fn effect(&self)  -> Result<Effect, Box<dyn Error>>{
-        Effect::try_from(self.string.as_str())
+    pub fn effect(&self, chip: Chip) -> Result<Effect, Box<dyn Error>>{
+        Effect::parse(self.string.as_str(), chip)
     }
 
-    pub fn from_str(str: &str) -> Self{
+    pub fn new(str: &str) -> Self{
         IdentNum { string: str.into() }
     }
 
@@ -420,7 +433,7 @@ impl IdentNum{
 
 impl From<&str> for IdentNum{
     fn from(str: &str) -> Self {
-        Self::from_str(str)
+        Self::new(str)
     }
 }
 